@@ -1,10 +1,34 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+layout(set = 0, binding = 0) uniform FragmentUniforms {
+    vec2 resolution;
+    float time;
+} uniforms;
+
+// These are filled in when the pipeline is created; see TRANSPARENT_WINDOW.
+layout(constant_id = 0) const float alpha = 1.0;
+layout(constant_id = 1) const bool premultiplied = false;
+
 layout(location = 0) in vec3 fragColor;
 
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    outColor = vec4(fragColor, 1.0);
+    // Pulse the brightness of the triangle over time.
+    float brightness = 0.75 + 0.25 * sin(uniforms.time);
+
+    vec3 color = fragColor * brightness;
+
+    // Darken pixels the further they are from the center of the screen.
+    vec2 uv = gl_FragCoord.xy / uniforms.resolution;
+    float vignette = smoothstep(0.8, 0.25, distance(uv, vec2(0.5)));
+    color *= vignette;
+
+    // Compositors that expect premultiplied alpha want the color scaled by it.
+    if (premultiplied) {
+        color *= alpha;
+    }
+
+    outColor = vec4(color, alpha);
 }
\ No newline at end of file
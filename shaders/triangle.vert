@@ -1,25 +1,41 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+layout(constant_id = 0) const float pointSize = 1.0;
+
 out gl_PerVertex {
     vec4 gl_Position;
+    float gl_PointSize;
 };
 
+layout(set = 0, binding = 1) uniform TransformUniforms {
+    mat4 model;
+    mat4 view;
+    mat4 projection;
+} transform;
+
 layout(location = 0) out vec3 fragColor;
 
-vec2 positions[3] = vec2[](
-    vec2(0.0, -0.5),
+// The corners of our quad, going counter-clockwise from the bottom left with Y
+// pointing up. Each corner is listed once; QUAD_INDICES in main.rs picks them
+// out for each triangle.
+vec2 positions[4] = vec2[](
+    vec2(-0.5, -0.5),
+    vec2(0.5, -0.5),
     vec2(0.5, 0.5),
     vec2(-0.5, 0.5)
 );
 
-vec3 colors[3] = vec3[](
+vec3 colors[4] = vec3[](
     vec3(1.0, 0.0, 0.0),
     vec3(0.0, 1.0, 0.0),
-    vec3(0.0, 0.0, 1.0)
+    vec3(0.0, 0.0, 1.0),
+    vec3(1.0, 1.0, 0.0)
 );
 
 void main() {
-    gl_Position = vec4(positions[gl_VertexIndex], 0.0, 1.0);
+    vec4 position = vec4(positions[gl_VertexIndex], 0.0, 1.0);
+    gl_Position = transform.projection * transform.view * transform.model * position;
+    gl_PointSize = pointSize;
     fragColor = colors[gl_VertexIndex];
 }
\ No newline at end of file
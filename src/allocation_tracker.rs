@@ -0,0 +1,212 @@
+// Vulkan lets us hand the driver our own functions for the host memory it
+// allocates on our behalf. We don't want to manage that memory any differently,
+// but it's a handy way to find out how much of it there is: AllocationTracker
+// counts every allocation, grouped by the scope the driver says it's for, and
+// can print a report when we're done.
+//
+// The driver frees memory without telling us how big it was, and can ask for
+// more alignment than a Vec<u8> promises, so every allocation has a small
+// header in front of it that remembers where the real block starts.
+
+use std::{mem, ptr};
+use std::sync::Mutex;
+
+use ash::vk;
+
+// Allocation scopes, in the order we report them.
+const SCOPES: [vk::SystemAllocationScope; 5] = [
+    vk::SystemAllocationScope::Command,
+    vk::SystemAllocationScope::Object,
+    vk::SystemAllocationScope::Cache,
+    vk::SystemAllocationScope::Device,
+    vk::SystemAllocationScope::Instance,
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ScopeStats {
+    allocations: usize,
+    bytes: usize,
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    scopes: [ScopeStats; 5],
+    live_bytes: usize,
+    peak_bytes: usize,
+
+    // Memory the driver allocated itself and only told us about.
+    internal_bytes: usize,
+}
+
+// Sits right before every pointer we hand to the driver.
+struct Header {
+    block: *mut u8,
+    capacity: usize,
+    size: usize,
+}
+
+pub struct AllocationTracker {
+    stats: Mutex<Stats>,
+    callbacks: vk::AllocationCallbacks,
+}
+
+impl AllocationTracker {
+    // The driver calls back into us with a pointer to the tracker, so it needs
+    // to stay put in memory; that's why we hand it out boxed.
+    pub fn new() -> Box<AllocationTracker> {
+        let mut tracker = Box::new(AllocationTracker {
+            stats: Mutex::new(Stats::default()),
+            callbacks: vk::AllocationCallbacks {
+                p_user_data: ptr::null_mut(),
+                pfn_allocation: allocation,
+                pfn_reallocation: reallocation,
+                pfn_free: free,
+                pfn_internal_allocation: internal_allocation,
+                pfn_internal_free: internal_free,
+            },
+        });
+
+        tracker.callbacks.p_user_data = &*tracker as *const AllocationTracker as *mut vk::c_void;
+        tracker
+    }
+
+    pub fn callbacks(&self) -> &vk::AllocationCallbacks {
+        &self.callbacks
+    }
+
+    pub fn report(&self) {
+        let stats = self.stats.lock().unwrap();
+
+        println!("Host allocations made by the driver:");
+
+        for (scope, scope_stats) in SCOPES.iter().zip(stats.scopes.iter()) {
+            println!(
+                "  {:?}: {} allocations, {} bytes",
+                scope,
+                scope_stats.allocations,
+                scope_stats.bytes
+            );
+        }
+
+        println!("  Peak: {} bytes", stats.peak_bytes);
+        println!("  Internal: {} bytes", stats.internal_bytes);
+
+        if stats.live_bytes > 0 {
+            println!("  Still allocated: {} bytes", stats.live_bytes);
+        }
+    }
+
+    fn record_allocation(&self, size: usize, scope: vk::SystemAllocationScope) {
+        let mut stats = self.stats.lock().unwrap();
+        let index = SCOPES.iter().position(|&known| known == scope).unwrap_or(0);
+
+        stats.scopes[index].allocations += 1;
+        stats.scopes[index].bytes += size;
+        stats.live_bytes += size;
+        stats.peak_bytes = stats.peak_bytes.max(stats.live_bytes);
+    }
+
+    fn record_free(&self, size: usize) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.live_bytes -= size;
+    }
+}
+
+unsafe fn tracker<'a>(user_data: *mut vk::c_void) -> &'a AllocationTracker {
+    &*(user_data as *const AllocationTracker)
+}
+
+unsafe fn allocate(size: usize, alignment: usize) -> *mut u8 {
+    // Alignments are always powers of two, so the bigger one is a multiple of
+    // the smaller one and our header ends up aligned too.
+    let alignment = alignment.max(mem::align_of::<Header>());
+    let header_size = mem::size_of::<Header>();
+    let capacity = header_size + alignment + size;
+
+    let mut block = Vec::<u8>::with_capacity(capacity);
+    let block_pointer = block.as_mut_ptr();
+    mem::forget(block);
+
+    let start = block_pointer as usize + header_size;
+    let aligned = (start + alignment - 1) & !(alignment - 1);
+
+    ptr::write((aligned - header_size) as *mut Header, Header {
+        block: block_pointer,
+        capacity: capacity,
+        size: size,
+    });
+
+    aligned as *mut u8
+}
+
+// Frees memory from allocate(), returning how big it was.
+unsafe fn deallocate(memory: *mut u8) -> usize {
+    let header = ptr::read((memory as *mut Header).offset(-1));
+    drop(Vec::from_raw_parts(header.block, 0, header.capacity));
+
+    header.size
+}
+
+unsafe extern "system" fn allocation(
+    user_data: *mut vk::c_void,
+    size: vk::size_t,
+    alignment: vk::size_t,
+    scope: vk::SystemAllocationScope,
+) -> *mut vk::c_void {
+    tracker(user_data).record_allocation(size, scope);
+
+    allocate(size, alignment) as *mut vk::c_void
+}
+
+unsafe extern "system" fn reallocation(
+    user_data: *mut vk::c_void,
+    original: *mut vk::c_void,
+    size: vk::size_t,
+    alignment: vk::size_t,
+    scope: vk::SystemAllocationScope,
+) -> *mut vk::c_void {
+    if original.is_null() {
+        return allocation(user_data, size, alignment, scope);
+    }
+
+    if size == 0 {
+        free(user_data, original);
+        return ptr::null_mut();
+    }
+
+    let original_size = (*(original as *mut Header).offset(-1)).size;
+
+    let memory = allocation(user_data, size, alignment, scope);
+    ptr::copy_nonoverlapping(original as *const u8, memory as *mut u8, original_size.min(size));
+    free(user_data, original);
+
+    memory
+}
+
+unsafe extern "system" fn free(user_data: *mut vk::c_void, memory: *mut vk::c_void) {
+    if memory.is_null() {
+        return;
+    }
+
+    let size = deallocate(memory as *mut u8);
+    tracker(user_data).record_free(size);
+}
+
+unsafe extern "system" fn internal_allocation(
+    user_data: *mut vk::c_void,
+    size: vk::size_t,
+    _allocation_type: vk::InternalAllocationType,
+    _scope: vk::SystemAllocationScope,
+) {
+    tracker(user_data).stats.lock().unwrap().internal_bytes += size;
+}
+
+unsafe extern "system" fn internal_free(
+    user_data: *mut vk::c_void,
+    size: vk::size_t,
+    _allocation_type: vk::InternalAllocationType,
+    _scope: vk::SystemAllocationScope,
+) {
+    let mut stats = tracker(user_data).stats.lock().unwrap();
+    stats.internal_bytes = stats.internal_bytes.saturating_sub(size);
+}
@@ -0,0 +1,198 @@
+// Every buffer goes through the same dance: create the buffer, ask it what kind
+// of memory it wants, find a memory type that fits, allocate, and bind. Getting
+// the memory type wrong is an easy mistake to make, so we do it in one place.
+//
+// BufferBuilder picks memory properties for us based on how the buffer is used.
+// Uniform buffers and transfer sources get written from the CPU, so they go in
+// host visible, coherent memory. Everything else goes in device local memory,
+// which is fastest for the GPU but can't be written from the CPU. host_visible()
+// overrides that choice when we know better.
+
+use std::{mem, ptr};
+
+use ash::{Device, vk};
+use ash::version::{DeviceV1_0, V1_0};
+
+// Vulkan hands out memory in 'types', each with different properties. To
+// allocate memory for a resource, we need to find a type that's both allowed
+// by the resource (type_bits) and has the properties we're looking for.
+pub fn find_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    flags: vk::MemoryPropertyFlags,
+) -> Option<u32> {
+    (0..memory_properties.memory_type_count).find(|&index| {
+        let allowed = type_bits & (1 << index) != 0;
+        let memory_type = &memory_properties.memory_types[index as usize];
+
+        allowed && memory_type.property_flags.subset(flags)
+    })
+}
+
+// A buffer along with the memory backing it.
+pub struct Buffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+
+    // The size we asked for, and the size of the allocation we actually got,
+    // which can be bigger.
+    pub size: vk::DeviceSize,
+    pub allocation_size: vk::DeviceSize,
+}
+
+impl Buffer {
+    // Copies data into the start of the buffer. This only works for buffers in
+    // host visible, coherent memory, since we don't flush our write.
+    pub fn write<T: Copy>(&self, device: &Device<V1_0>, data: &[T]) {
+        let size = mem::size_of_val(data) as vk::DeviceSize;
+        assert!(size <= self.size, "Data doesn't fit in the buffer!");
+
+        unsafe {
+            let pointer = device.map_memory(self.memory, 0, size, vk::MemoryMapFlags::empty())
+                .expect("Unable to map buffer memory!");
+
+            ptr::copy_nonoverlapping(data.as_ptr(), pointer as *mut T, data.len());
+
+            device.unmap_memory(self.memory);
+        }
+    }
+
+    // If the memory is still mapped, it's unmapped implicitly when it's freed.
+    // The allocation callbacks need to match the ones we were built with.
+    pub fn destroy(&self, device: &Device<V1_0>, allocation_callbacks: Option<&vk::AllocationCallbacks>) {
+        unsafe {
+            device.destroy_buffer(self.buffer, allocation_callbacks);
+            device.free_memory(self.memory, allocation_callbacks);
+        }
+    }
+}
+
+pub struct BufferBuilder {
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    memory_flags: vk::MemoryPropertyFlags,
+}
+
+impl BufferBuilder {
+    pub fn new(size: vk::DeviceSize, usage: vk::BufferUsageFlags) -> BufferBuilder {
+        let host_written = vk::BUFFER_USAGE_UNIFORM_BUFFER_BIT | vk::BUFFER_USAGE_TRANSFER_SRC_BIT;
+
+        let memory_flags = if usage.intersects(host_written) {
+            vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT
+        } else {
+            vk::MEMORY_PROPERTY_DEVICE_LOCAL_BIT
+        };
+
+        BufferBuilder {
+            size: size,
+            usage: usage,
+            memory_flags: memory_flags,
+        }
+    }
+
+    // Puts the buffer in memory we can map and write to from the CPU. Without
+    // coherent memory, writes have to be flushed before the GPU sees them.
+    pub fn host_visible(mut self, coherent: bool) -> BufferBuilder {
+        self.memory_flags = if coherent {
+            vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT | vk::MEMORY_PROPERTY_HOST_COHERENT_BIT
+        } else {
+            vk::MEMORY_PROPERTY_HOST_VISIBLE_BIT
+        };
+
+        self
+    }
+
+    pub fn build(
+        &self,
+        device: &Device<V1_0>,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> Buffer {
+        let buffer_info = vk::BufferCreateInfo {
+            s_type: vk::StructureType::BufferCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            size: self.size,
+            usage: self.usage,
+            sharing_mode: vk::SharingMode::Exclusive,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+        };
+
+        let buffer = unsafe {
+            device.create_buffer(&buffer_info, allocation_callbacks)
+                .expect("Unable to create buffer!")
+        };
+
+        let memory_requirements = device.get_buffer_memory_requirements(buffer);
+
+        let memory_type = find_memory_type(
+            memory_properties,
+            memory_requirements.memory_type_bits,
+            self.memory_flags,
+        ).expect("Unable to find a suitable memory type for buffer!");
+
+        let allocate_info = vk::MemoryAllocateInfo {
+            s_type: vk::StructureType::MemoryAllocateInfo,
+            p_next: ptr::null(),
+            allocation_size: memory_requirements.size,
+            memory_type_index: memory_type,
+        };
+
+        let memory = unsafe {
+            device.allocate_memory(&allocate_info, allocation_callbacks)
+                .expect("Unable to allocate buffer memory!")
+        };
+
+        unsafe {
+            device.bind_buffer_memory(buffer, memory, 0)
+                .expect("Unable to bind buffer memory!");
+        }
+
+        Buffer {
+            buffer: buffer,
+            memory: memory,
+            size: self.size,
+            allocation_size: memory_requirements.size,
+        }
+    }
+}
+
+// A buffer full of 16 bit indices. Indexed drawing lets vertices that are
+// shared between triangles be stored once and referred to by index, instead of
+// being repeated for every triangle that uses them.
+//
+// 16 bit indices can refer to up to 65536 vertices, which is plenty for us.
+pub struct IndexBuffer {
+    pub buffer: Buffer,
+    pub count: u32,
+}
+
+impl IndexBuffer {
+    // Our indices never change, so we keep things simple and put them in
+    // host visible memory instead of copying them into device local memory
+    // through a staging buffer.
+    pub fn new(
+        device: &Device<V1_0>,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        indices: &[u16],
+        allocation_callbacks: Option<&vk::AllocationCallbacks>,
+    ) -> IndexBuffer {
+        let size = mem::size_of_val(indices) as vk::DeviceSize;
+
+        let buffer = BufferBuilder::new(size, vk::BUFFER_USAGE_INDEX_BUFFER_BIT)
+            .host_visible(true)
+            .build(device, memory_properties, allocation_callbacks);
+
+        buffer.write(device, indices);
+
+        IndexBuffer {
+            buffer: buffer,
+            count: indices.len() as u32,
+        }
+    }
+
+    pub fn destroy(&self, device: &Device<V1_0>, allocation_callbacks: Option<&vk::AllocationCallbacks>) {
+        self.buffer.destroy(device, allocation_callbacks);
+    }
+}
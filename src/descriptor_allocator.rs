@@ -0,0 +1,148 @@
+// Sizing descriptor pools by hand is a pain: ask for too little and allocations
+// start failing, ask for too much and you're wasting memory. This hands out
+// descriptor sets and quietly creates a new, bigger pool whenever the current
+// one fills up.
+//
+// Core Vulkan 1.0 (which is what our bindings cover) doesn't promise a useful
+// error when a pool runs out, so we count how many sets we've taken from each
+// pool ourselves instead of waiting for an allocation to fail.
+
+use std::ptr;
+
+use ash::{Device, vk};
+use ash::version::{DeviceV1_0, V1_0};
+
+pub struct DescriptorAllocator<'a> {
+    // How many of each type of descriptor a single set needs. Every set we
+    // allocate is assumed to fit in this.
+    descriptors_per_set: Vec<vk::DescriptorPoolSize>,
+
+    // Every pool we've created so far, along with how many sets each one can
+    // hold. Pools before 'current' are full.
+    pools: Vec<(vk::DescriptorPool, u32)>,
+    current: usize,
+
+    // How many sets we've handed out from the current pool.
+    allocated_from_current: u32,
+
+    // How many sets the next pool we create will hold.
+    next_pool_sets: u32,
+
+    // Every pool is created and destroyed with these.
+    allocation_callbacks: Option<&'a vk::AllocationCallbacks>,
+}
+
+impl<'a> DescriptorAllocator<'a> {
+    pub fn new(
+        descriptors_per_set: &[vk::DescriptorPoolSize],
+        initial_sets: u32,
+        allocation_callbacks: Option<&'a vk::AllocationCallbacks>,
+    ) -> DescriptorAllocator<'a> {
+        DescriptorAllocator {
+            descriptors_per_set: descriptors_per_set.to_vec(),
+            pools: Vec::new(),
+            current: 0,
+            allocated_from_current: 0,
+            next_pool_sets: initial_sets.max(1),
+            allocation_callbacks: allocation_callbacks,
+        }
+    }
+
+    pub fn allocate(&mut self, device: &Device<V1_0>, layout: vk::DescriptorSetLayout) -> vk::DescriptorSet {
+        loop {
+            let has_room = match self.pools.get(self.current) {
+                Some(&(_, max_sets)) => self.allocated_from_current < max_sets,
+                None => false,
+            };
+
+            if !has_room {
+                self.advance(device);
+            }
+
+            let pool = self.pools[self.current].0;
+
+            let allocate_info = vk::DescriptorSetAllocateInfo {
+                s_type: vk::StructureType::DescriptorSetAllocateInfo,
+                p_next: ptr::null(),
+                descriptor_pool: pool,
+                descriptor_set_count: 1,
+                p_set_layouts: &layout,
+            };
+
+            let result = unsafe {
+                device.allocate_descriptor_sets(&allocate_info)
+            };
+
+            match result {
+                Ok(sets) => {
+                    self.allocated_from_current += 1;
+                    return sets[0];
+                },
+
+                // The pool has room on paper, but it's too fragmented to
+                // give us a set. Treat it as full and move on to the next.
+                Err(vk::Result::ErrorFragmentedPool) => {
+                    self.advance(device);
+                },
+
+                Err(error) => panic!("Unable to allocate descriptor set: {:?}", error),
+            }
+        }
+    }
+
+    pub fn destroy(&mut self, device: &Device<V1_0>) {
+        for &(pool, _) in &self.pools {
+            unsafe {
+                device.destroy_descriptor_pool(pool, self.allocation_callbacks);
+            }
+        }
+
+        self.pools.clear();
+        self.current = 0;
+        self.allocated_from_current = 0;
+    }
+
+    // Moves on to the next pool, creating one if we've used up all of them.
+    fn advance(&mut self, device: &Device<V1_0>) {
+        if !self.pools.is_empty() {
+            self.current += 1;
+        }
+
+        self.allocated_from_current = 0;
+
+        if self.current < self.pools.len() {
+            return;
+        }
+
+        let max_sets = self.next_pool_sets;
+
+        let pool_sizes = self.descriptors_per_set
+            .iter()
+            .map(|size| vk::DescriptorPoolSize {
+                typ: size.typ,
+                descriptor_count: size.descriptor_count * max_sets,
+            })
+            .collect::<Vec<_>>();
+
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: vk::StructureType::DescriptorPoolCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            max_sets: max_sets,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+        };
+
+        let pool = unsafe {
+            device.create_descriptor_pool(&pool_info, self.allocation_callbacks)
+                .expect("Unable to create descriptor pool!")
+        };
+
+        self.pools.push((pool, max_sets));
+        self.current = self.pools.len() - 1;
+
+        // Each new pool is twice as big as the last, so we don't end up with
+        // a long list of tiny pools.
+        self.next_pool_sets = max_sets * 2;
+    }
+}
@@ -0,0 +1,92 @@
+// Device features have to be turned on explicitly when we create our device,
+// and asking for one the device doesn't support makes device creation fail.
+// This sorts the features we'd like into ones we can have and ones we can't.
+//
+// PhysicalDeviceFeatures is a struct with a field for every feature, so we name
+// the ones we care about with an enum instead of passing field names around.
+// Add to it as the example grows!
+
+use ash::vk;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    DepthBiasClamp,
+    FillModeNonSolid,
+    LargePoints,
+    RobustBufferAccess,
+    WideLines,
+}
+
+impl Feature {
+    fn field(self, features: &mut vk::PhysicalDeviceFeatures) -> &mut vk::Bool32 {
+        match self {
+            Feature::DepthBiasClamp => &mut features.depth_bias_clamp,
+            Feature::FillModeNonSolid => &mut features.fill_mode_non_solid,
+            Feature::LargePoints => &mut features.large_points,
+            Feature::RobustBufferAccess => &mut features.robust_buffer_access,
+            Feature::WideLines => &mut features.wide_lines,
+        }
+    }
+
+    fn is_supported(self, supported: &vk::PhysicalDeviceFeatures) -> bool {
+        let mut supported = supported.clone();
+        *self.field(&mut supported) == vk::VK_TRUE
+    }
+}
+
+// The features we ended up turning on, ready to hand to DeviceCreateInfo.
+pub struct EnabledFeatures {
+    features: vk::PhysicalDeviceFeatures,
+    enabled: Vec<Feature>,
+}
+
+impl EnabledFeatures {
+    pub fn contains(&self, feature: Feature) -> bool {
+        self.enabled.contains(&feature)
+    }
+
+    pub fn enabled(&self) -> &[Feature] {
+        &self.enabled
+    }
+
+    pub fn features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.features
+    }
+}
+
+// Turns on every required feature and as many optional ones as the device
+// supports. If any required features are missing, we return those instead.
+pub fn negotiate(
+    supported: &vk::PhysicalDeviceFeatures,
+    required: &[Feature],
+    optional: &[Feature],
+) -> Result<EnabledFeatures, Vec<Feature>> {
+    let missing = required
+        .iter()
+        .cloned()
+        .filter(|feature| !feature.is_supported(supported))
+        .collect::<Vec<_>>();
+
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+
+    let mut enabled = required.to_vec();
+
+    for &feature in optional {
+        if feature.is_supported(supported) && !enabled.contains(&feature) {
+            enabled.push(feature);
+        }
+    }
+
+    let mut features = vk::PhysicalDeviceFeatures::default();
+
+    for &feature in &enabled {
+        *feature.field(&mut features) = vk::VK_TRUE;
+    }
+
+    Ok(EnabledFeatures {
+        features: features,
+        enabled: enabled,
+    })
+}
@@ -0,0 +1,88 @@
+// Hooks into RenderDoc's in-application API so that we can capture a frame by
+// pressing a key instead of launching the whole program through RenderDoc's UI.
+//
+// This is only compiled in with the 'renderdoc' feature. Without it, or when
+// RenderDoc isn't attached to the process, every method here does nothing.
+
+#[cfg(feature = "renderdoc")]
+use std::ptr;
+
+#[cfg(feature = "renderdoc")]
+use renderdoc::{RenderDoc, V110};
+#[cfg(feature = "renderdoc")]
+use renderdoc::prelude::*;
+
+pub struct FrameCapture {
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDoc<V110>>,
+
+    capture_requested: bool,
+    capturing: bool,
+}
+
+impl FrameCapture {
+    #[cfg(feature = "renderdoc")]
+    pub fn new() -> FrameCapture {
+        // Loading the API only succeeds if RenderDoc injected itself into our
+        // process, which is exactly when we want captures to work.
+        let renderdoc = RenderDoc::new().ok();
+
+        if renderdoc.is_some() {
+            println!("RenderDoc attached, press F11 to capture a frame.");
+        }
+
+        FrameCapture {
+            renderdoc: renderdoc,
+            capture_requested: false,
+            capturing: false,
+        }
+    }
+
+    #[cfg(not(feature = "renderdoc"))]
+    pub fn new() -> FrameCapture {
+        FrameCapture {
+            capture_requested: false,
+            capturing: false,
+        }
+    }
+
+    // Asks for the next frame to be captured.
+    pub fn request(&mut self) {
+        self.capture_requested = true;
+    }
+
+    // Called right before we submit a frame's work.
+    pub fn begin_frame(&mut self) {
+        if !self.capture_requested {
+            return;
+        }
+
+        self.capture_requested = false;
+
+        #[cfg(feature = "renderdoc")]
+        {
+            if let Some(ref mut renderdoc) = self.renderdoc {
+                // Null device and window handles mean "whatever is active".
+                renderdoc.start_frame_capture(ptr::null(), ptr::null());
+                self.capturing = true;
+            }
+        }
+    }
+
+    // Called right after we present a frame.
+    pub fn end_frame(&mut self) {
+        if !self.capturing {
+            return;
+        }
+
+        self.capturing = false;
+
+        #[cfg(feature = "renderdoc")]
+        {
+            if let Some(ref mut renderdoc) = self.renderdoc {
+                renderdoc.end_frame_capture(ptr::null(), ptr::null());
+                println!("Captured a frame with RenderDoc.");
+            }
+        }
+    }
+}
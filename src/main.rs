@@ -3,67 +3,653 @@ extern crate ash;
 extern crate cgmath;
 extern crate winapi;
 extern crate winit;
-
+#[cfg(feature = "renderdoc")]
+extern crate renderdoc;
+
+mod allocation_tracker;
+mod buffer;
+mod descriptor_allocator;
+mod features;
+mod frame_capture;
+mod pipeline;
+mod platform;
+mod profile;
+
+use std::collections::HashMap;
 use std::default::Default;
 use std::ffi::{CStr, CString};
 use std::ptr;
+use std::sync::{Arc, Mutex};
 
-use ash::{Entry, Instance, Device, vk};
+use ash::{Entry, Instance, InstanceError, Device, DeviceError, vk};
 use ash::version::{DeviceV1_0, EntryV1_0, InstanceV1_0, V1_0};
-use ash::extensions::{DebugReport, Surface, Swapchain, Win32Surface};
+use ash::extensions::{DebugReport, Surface, Swapchain};
+use cgmath::{Deg, Matrix4, Point3, Rad, Vector3};
+
+use allocation_tracker::AllocationTracker;
+use buffer::{BufferBuilder, IndexBuffer};
+use descriptor_allocator::DescriptorAllocator;
+use features::Feature;
+use frame_capture::FrameCapture;
+use pipeline::{DepthBias, GraphicsPipelineBuilder, ShaderStage};
+use profile::ProfileZone;
 
 // Rust lets us statically embed build artifacts into our binary. Neat!
 static VERTEX_SHADER: &'static [u8] = include_bytes!("../built-shaders/triangle-vert.spv");
 static FRAGMENT_SHADER: &'static [u8] = include_bytes!("../built-shaders/triangle-frag.spv");
 
-// A set of platform-specific instance extensions.
+// Our vertex data lives in triangle.vert, which looks up each vertex's position
+// and color using gl_VertexIndex. We draw a quad out of two triangles, which
+// share two of their corners, so instead of listing those corners twice in the
+// shader we list each one once and stitch triangles together with indices.
+//
+// Every index needs to be smaller than the length of the arrays in that shader,
+// or we'll read past the end! Both triangles wind the same way as the corners
+// in the shader: counter-clockwise with Y pointing up. Our projection flips Y
+// for Vulkan, so they stay counter-clockwise on screen to match FRONT_FACE.
+const QUAD_INDICES: [u16; 6] = [
+    0, 1, 2,
+    2, 3, 0,
+];
+
+// Which triangles get culled, and which winding order counts as 'front'.
 //
-// I don't have another machine to test other implementations, so only a Windows
-// implementation is provided right now.
-#[cfg(all(windows))]
-fn extension_names() -> Vec<*const i8> {
-    vec![
-        Surface::name().as_ptr(),
-        DebugReport::name().as_ptr(),
-        Win32Surface::name().as_ptr(),
-    ]
+// Winding order is decided in framebuffer coordinates, after the viewport
+// transform. Vulkan's Y axis points down, unlike OpenGL's, so geometry authored
+// counter-clockwise for OpenGL (cgmath and most model formats) shows up
+// clockwise here unless the projection or viewport flips Y. Our projection
+// does (see TransformUniforms), so our counter-clockwise quad stays that way.
+// If your geometry disappears, try flipping FRONT_FACE or setting CULL_MODE to
+// vk::CullModeFlags::empty().
+const CULL_MODE: vk::CullModeFlags = vk::CULL_MODE_BACK_BIT;
+const FRONT_FACE: vk::FrontFace = vk::FrontFace::CounterClockwise;
+
+// How wide lines are, in pixels. Only 1.0 is guaranteed to work; anything else,
+// thinner lines included, needs the 'wide lines' device feature, and is limited
+// to the range the device reports. This only matters when we draw lines instead
+// of filled triangles.
+const LINE_WIDTH: f32 = 1.0;
+
+// How triangles get rasterized: Fill draws them solid, Line draws their edges,
+// and Point draws only their vertices. Anything other than Fill needs the
+// 'fill mode non-solid' device feature.
+const POLYGON_MODE: vk::PolygonMode = vk::PolygonMode::Fill;
+
+// How big points are, in pixels, when POLYGON_MODE is Point. The vertex shader
+// writes this to gl_PointSize. Sizes other than 1.0 need the 'large points'
+// device feature, and are limited to the range the device reports.
+const POINT_SIZE: f32 = 1.0;
+
+// We build a pipeline for each of these up front, so that switching between
+// them with the P key doesn't stall while a new pipeline gets compiled. The
+// one matching POLYGON_MODE is active when we start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PipelineVariant {
+    Filled,
+    Wireframe,
+    Points,
+}
+
+const PIPELINE_VARIANTS: [PipelineVariant; 3] = [
+    PipelineVariant::Filled,
+    PipelineVariant::Wireframe,
+    PipelineVariant::Points,
+];
+
+impl PipelineVariant {
+    fn polygon_mode(self) -> vk::PolygonMode {
+        match self {
+            PipelineVariant::Filled => vk::PolygonMode::Fill,
+            PipelineVariant::Wireframe => vk::PolygonMode::Line,
+            PipelineVariant::Points => vk::PolygonMode::Point,
+        }
+    }
+}
+
+// A debugging aid: when set, we only clear the screen and never draw anything.
+// If you see the clear color in this mode but nothing when drawing normally,
+// the swapchain and present loop are fine and the problem lies somewhere in
+// the pipeline or the geometry. Press C to toggle this while running.
+const CLEAR_ONLY: bool = false;
+
+// Flips the Y axis with a negative viewport height (see VK_KHR_maintenance1) so
+// that clip space matches OpenGL-style math without touching any matrices.
+//
+// TransformUniforms already flips Y in its projection matrix, so turning this on
+// as well flips it back: our quad shows up upside down, and since its winding
+// order is reversed too, FRONT_FACE needs to become Clockwise for it to survive
+// culling.
+const FLIP_VIEWPORT_Y: bool = false;
+
+// Depth bias nudges the depth of each fragment, which shadow mapping uses to
+// avoid surfaces shadowing themselves. It's off unless DEPTH_BIAS is set.
+//
+// A clamp other than 0.0 needs the 'depth bias clamp' device feature; without
+// it, we leave the bias unclamped.
+const DEPTH_BIAS: Option<DepthBias> = None;
+
+// When this is set, we ask the window system for a transparent window and draw
+// our triangle partially see-through. Whether this works depends heavily on the
+// compositor you're running under!
+const TRANSPARENT_WINDOW: bool = false;
+const TRANSPARENT_TRIANGLE_ALPHA: f32 = 0.5;
+
+// Specialization constants for triangle.frag, which let us tweak the shader's
+// behavior when the pipeline is created without compiling a new shader.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FragmentSpecialization {
+    alpha: f32,
+    premultiplied: vk::Bool32,
+}
+
+// How many images we'd like in our swapchain. Fewer images means lower latency,
+// more means smoother frame pacing. None picks one more than the minimum; any
+// other value gets clamped to what the surface supports.
+const DESIRED_SWAPCHAIN_IMAGES: Option<u32> = None;
+
+// How we're going to use our swapchain images. We only ever render into them,
+// but features like screenshots (TRANSFER_SRC) or compute post-processing
+// (STORAGE) need to add their bits here.
+const SWAPCHAIN_IMAGE_USAGE: vk::ImageUsageFlags = vk::IMAGE_USAGE_COLOR_ATTACHMENT_BIT;
+
+// How we'd like our frames to be presented:
+//
+// - Vsync waits for vertical blank and never tears (Fifo)
+// - LowLatency replaces queued frames with newer ones instead of waiting for
+//   them to be shown, so it doesn't tear either (Mailbox)
+// - Immediate shows frames right away, and can tear (Immediate)
+// - Adaptive waits for vertical blank unless we're running late, in which case
+//   it tears instead of stuttering (FifoRelaxed)
+//
+// Everything but Vsync is optional, so each one falls back to Fifo.
+// Only the variant named by PRESENT_PREFERENCE gets constructed; edit it to try
+// the others.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PresentPreference {
+    Vsync,
+    LowLatency,
+    Immediate,
+    Adaptive,
+}
+
+impl PresentPreference {
+    // Present modes to try, in order.
+    fn present_modes(self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            PresentPreference::Vsync => &[vk::PresentModeKHR::Fifo],
+            PresentPreference::LowLatency => &[vk::PresentModeKHR::Mailbox, vk::PresentModeKHR::Fifo],
+            PresentPreference::Immediate => &[
+                vk::PresentModeKHR::Immediate,
+                vk::PresentModeKHR::Mailbox,
+                vk::PresentModeKHR::Fifo,
+            ],
+            PresentPreference::Adaptive => &[vk::PresentModeKHR::FifoRelaxed, vk::PresentModeKHR::Fifo],
+        }
+    }
+}
+
+const PRESENT_PREFERENCE: PresentPreference = PresentPreference::LowLatency;
+
+// Host coherent memory makes our CPU writes visible to the GPU automatically.
+// Turning this off uses memory that's only host visible, which means we have to
+// flush our writes ourselves. Some devices have very little coherent memory, so
+// it's worth knowing how to do both.
+const COHERENT_UNIFORM_MEMORY: bool = true;
+
+// Out of bounds buffer accesses in shaders are undefined behavior, and tend to
+// show up as random corruption or device loss. Robust buffer access makes reads
+// return zero and discards writes instead, which is much easier to debug. It
+// costs some performance, so we only ask for it in debug builds.
+const ROBUST_BUFFER_ACCESS: bool = cfg!(debug_assertions);
+
+// When set, we pass the driver our own host allocation callbacks for every object
+// we create, and print a breakdown of how much memory it asked for when we exit.
+const TRACK_HOST_ALLOCATIONS: bool = false;
+
+// Device features we can't run without. Devices missing any of these are
+// rejected when we create our device. Everything else we use is optional, and
+// we fall back to something simpler when it's missing.
+const REQUIRED_FEATURES: &[Feature] = &[];
+
+// Continuous redraws as fast as the swapchain lets us, which is what anything
+// animated wants. OnDemand sleeps until the window gets an event worth
+// redrawing for, like a resize or a key press, which is much kinder to laptop
+// batteries when nothing on screen is changing.
+// Only the variant named by EVENT_LOOP_MODE gets constructed; edit it to try
+// the other.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EventLoopMode {
+    Continuous,
+    OnDemand,
+}
+
+const EVENT_LOOP_MODE: EventLoopMode = EventLoopMode::Continuous;
+
+// The data our fragment shader reads out of its uniform buffer. `repr(C)` makes
+// sure the layout matches the `FragmentUniforms` block in triangle.frag.
+//
+// Uniform blocks follow GLSL's std140 layout rules, where a vec2 has to start
+// on an 8 byte boundary. Putting it first keeps both sides lined up without
+// any explicit padding.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FragmentUniforms {
+    resolution: [f32; 2],
+    time: f32,
+}
+
+// The matrices our vertex shader uses to place the quad on screen, matching the
+// `TransformUniforms` block in triangle.vert. cgmath stores matrices column by
+// column, which is what GLSL expects, and a mat4 needs no padding under std140.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct TransformUniforms {
+    model: Matrix4<f32>,
+    view: Matrix4<f32>,
+    projection: Matrix4<f32>,
+}
+
+impl TransformUniforms {
+    // Spins the quad around its center while a camera looks at it head on.
+    fn new(time: f32, aspect_ratio: f32) -> TransformUniforms {
+        // cgmath builds projections for OpenGL, where Y points up and depth
+        // goes from -1 to 1. Vulkan's Y points down and its depth goes from 0
+        // to 1, so we flip Y and squash depth after projecting.
+        let opengl_to_vulkan = Matrix4::new(
+            1.0, 0.0, 0.0, 0.0,
+            0.0, -1.0, 0.0, 0.0,
+            0.0, 0.0, 0.5, 0.0,
+            0.0, 0.0, 0.5, 1.0,
+        );
+
+        TransformUniforms {
+            model: Matrix4::from_angle_z(Rad(time)),
+            view: Matrix4::look_at(
+                Point3::new(0.0, 0.0, 2.0),
+                Point3::new(0.0, 0.0, 0.0),
+                Vector3::unit_y(),
+            ),
+            projection: opengl_to_vulkan * cgmath::perspective(Deg(45.0), aspect_ratio, 0.1, 10.0),
+        }
+    }
+}
+
+// Vulkan wants SPIR-V as a slice of u32 words, but include_bytes! only promises
+// us bytes with no particular alignment. Casting those bytes to *const u32 is
+// undefined behavior if they happen to land on an odd address, so we copy them
+// into a buffer of words instead.
+fn spirv_words(bytes: &[u8]) -> Vec<u32> {
+    assert!(bytes.len() % 4 == 0, "SPIR-V length must be a multiple of 4 bytes!");
+
+    let mut words = vec![0u32; bytes.len() / 4];
+
+    unsafe {
+        ptr::copy_nonoverlapping(bytes.as_ptr(), words.as_mut_ptr() as *mut u8, bytes.len());
+    }
+
+    words
 }
 
-// Uses a platform specific extension to create a surface. Like the
-// extension_names() method, it's only implemented for Windows right now.
-#[cfg(windows)]
-fn create_surface(
-    entry: &Entry<V1_0>,
+// Not every format can be used for everything: a format might be fine to sample
+// from but not to render into, for example. This walks through a list of formats
+// in order of preference and returns the first one that supports the features
+// we need with the given tiling.
+fn find_supported_format(
     instance: &Instance<V1_0>,
-    window: &winit::Window,
-) -> Result<vk::SurfaceKHR, vk::Result> {
-    use winapi::shared::windef::HWND;
-    use winapi::um::winuser::GetWindow;
-    use winit::os::windows::WindowExt;
+    physical_device: vk::PhysicalDevice,
+    candidates: &[vk::Format],
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+) -> Option<vk::Format> {
+    candidates
+        .iter()
+        .cloned()
+        .find(|&format| {
+            let properties = instance.get_physical_device_format_properties(physical_device, format);
+
+            match tiling {
+                vk::ImageTiling::Linear => properties.linear_tiling_features.subset(features),
+                vk::ImageTiling::Optimal => properties.optimal_tiling_features.subset(features),
+            }
+        })
+}
 
-    let hwnd = window.get_hwnd() as HWND;
-    let hinstance = unsafe {
-        GetWindow(hwnd, 0) as *const vk::c_void
+// Checks that a physical device supports every device extension we need. It's
+// much nicer to skip a device here than to have device creation fail later.
+fn check_device_extension_support(
+    instance: &Instance<V1_0>,
+    physical_device: vk::PhysicalDevice,
+    required: &[&CStr],
+) -> bool {
+    let available = match instance.enumerate_device_extension_properties(physical_device) {
+        Ok(available) => available,
+        Err(_) => return false,
     };
 
-    let win32_create_info = vk::Win32SurfaceCreateInfoKHR {
-        s_type: vk::StructureType::Win32SurfaceCreateInfoKhr,
+    required.iter().all(|&required_name| {
+        available.iter().any(|extension| {
+            let name = unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) };
+            name == required_name
+        })
+    })
+}
+
+// Prints everything we know about a physical device that could matter when
+// picking one: its queue families, what it can present to our surface, and
+// its memory. Used by --verbose-init.
+fn print_device_diagnostics(
+    instance: &Instance<V1_0>,
+    surface_extension: &Surface,
+    surface: vk::SurfaceKHR,
+    physical_device: vk::PhysicalDevice,
+) {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
+
+    println!("Device {:?} ({:?})", name, properties.device_type);
+
+    println!("  Queue families:");
+    println!("    index  count  present  flags");
+
+    let queue_families = instance.get_physical_device_queue_family_properties(physical_device);
+
+    for (index, family) in queue_families.iter().enumerate() {
+        let supports_present = surface_extension.get_physical_device_surface_support_khr(
+            physical_device,
+            index as u32,
+            surface,
+        );
+
+        println!(
+            "    {:>5}  {:>5}  {:>7}  {:?}",
+            index,
+            family.queue_count,
+            if supports_present { "yes" } else { "no" },
+            family.queue_flags
+        );
+    }
+
+    println!("  Surface formats:");
+
+    match surface_extension.get_physical_device_surface_formats_khr(physical_device, surface) {
+        Ok(formats) => {
+            for format in &formats {
+                println!("    {:?} ({:?})", format.format, format.color_space);
+            }
+        },
+        Err(error) => println!("    Unable to query: {:?}", error),
+    }
+
+    println!("  Present modes:");
+
+    match surface_extension.get_physical_device_surface_present_modes_khr(physical_device, surface) {
+        Ok(modes) => {
+            for mode in &modes {
+                println!("    {:?}", mode);
+            }
+        },
+        Err(error) => println!("    Unable to query: {:?}", error),
+    }
+
+    let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+
+    println!("  Memory heaps:");
+    println!("    index   size (MB)  flags");
+
+    for index in 0..memory_properties.memory_heap_count as usize {
+        let heap = &memory_properties.memory_heaps[index];
+
+        println!("    {:>5}  {:>10}  {:?}", index, heap.size / (1024 * 1024), heap.flags);
+    }
+
+    println!("  Memory types:");
+    println!("    index  heap  flags");
+
+    for index in 0..memory_properties.memory_type_count as usize {
+        let memory_type = &memory_properties.memory_types[index];
+
+        println!("    {:>5}  {:>4}  {:?}", index, memory_type.heap_index, memory_type.property_flags);
+    }
+}
+
+// Every value given after `flag` on the command line, for options that can be
+// passed more than once, like `--layer A --layer B`.
+fn arg_values(args: &[String], flag: &str) -> Vec<String> {
+    args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].clone())
+        .collect()
+}
+
+// How much we'd like to use a given surface format, higher being better.
+//
+// We want an sRGB format, so that the hardware converts our linear colors when
+// it writes them out and blending happens in linear space. If we can't get
+// one, any format in the sRGB color space will do, and after that we'll take
+// whatever's left in the order the surface listed them.
+fn surface_format_score(surface_format: &vk::SurfaceFormatKHR) -> u32 {
+    if surface_format.color_space != vk::ColorSpaceKHR::SrgbNonlinear {
+        return 0;
+    }
+
+    match surface_format.format {
+        vk::Format::B8g8r8a8Srgb => 3,
+        vk::Format::R8g8b8a8Srgb => 2,
+        _ => 1,
+    }
+}
+
+// Right after a driver update or a GPU reset, creating an instance or device can
+// fail once and then succeed moments later. We give those calls a few tries,
+// waiting a little longer each time, before we give up for real.
+const CREATION_ATTEMPTS: u32 = 3;
+
+fn is_transient_failure(result: vk::Result) -> bool {
+    result == vk::Result::ErrorInitializationFailed || result == vk::Result::ErrorDeviceLost
+}
+
+fn retry_transient<T, E, F, P>(mut create: F, is_transient: P) -> Result<T, E>
+where
+    F: FnMut() -> Result<T, E>,
+    P: Fn(&E) -> bool,
+    E: std::fmt::Debug,
+{
+    let mut attempt = 1;
+
+    loop {
+        match create() {
+            Err(ref error) if attempt < CREATION_ATTEMPTS && is_transient(error) => {
+                println!("Attempt {} failed with {:?}, retrying...", attempt, error);
+                std::thread::sleep(std::time::Duration::from_millis(100 * attempt as u64));
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+// Which queue family we use for each kind of work. On most devices these are
+// all the same family, but they don't have to be.
+#[derive(Debug, Clone, Copy)]
+struct QueueFamilies {
+    graphics: u32,
+    present: u32,
+}
+
+// The queues we pulled out of each of our queue families, fetched once right
+// after creating our device.
+struct Queues {
+    graphics: vk::Queue,
+    present: vk::Queue,
+}
+
+impl QueueFamilies {
+    fn is_shared(&self) -> bool {
+        self.graphics == self.present
+    }
+
+    // Each family we use, listed once. Asking for queues from the same family
+    // twice when creating a device isn't allowed.
+    fn unique_indices(&self) -> Vec<u32> {
+        if self.is_shared() {
+            vec![self.graphics]
+        } else {
+            vec![self.graphics, self.present]
+        }
+    }
+
+    // We only ever use the first queue in each family.
+    fn get_queues(&self, device: &Device<V1_0>) -> Queues {
+        unsafe {
+            Queues {
+                graphics: device.get_device_queue(self.graphics, 0),
+                present: device.get_device_queue(self.present, 0),
+            }
+        }
+    }
+}
+
+// Everything we need to know to record the commands that draw a frame. When any
+// of this changes, the command buffers have to be recorded again.
+struct DrawInfo<'a> {
+    render_pass: vk::RenderPass,
+    framebuffers: &'a [vk::Framebuffer],
+    extent: vk::Extent2D,
+    viewport: vk::Viewport,
+    scissor: vk::Rect2D,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_sets: &'a [vk::DescriptorSet],
+    index_buffer: vk::Buffer,
+    index_count: u32,
+
+    // A debugging aid, see CLEAR_ONLY.
+    clear_only: bool,
+}
+
+// Allocates and records one command buffer per framebuffer. We record them once
+// up front and replay them every frame, since what we draw rarely changes.
+fn record_command_buffers(
+    device: &Device<V1_0>,
+    command_pool: vk::CommandPool,
+    draw_info: &DrawInfo,
+) -> Vec<vk::CommandBuffer> {
+    let command_buffers_info = vk::CommandBufferAllocateInfo {
+        s_type: vk::StructureType::CommandBufferAllocateInfo,
         p_next: ptr::null(),
-        flags: Default::default(),
-        hinstance: hinstance,
-        hwnd: hwnd as *const vk::c_void,
+        command_pool: command_pool,
+        level: vk::CommandBufferLevel::Primary,
+        command_buffer_count: draw_info.framebuffers.len() as u32,
+    };
+
+    let command_buffers = unsafe {
+        device.allocate_command_buffers(&command_buffers_info)
+            .expect("Unable to allocate command buffers!")
     };
 
-    let win32_surface_extension = Win32Surface::new(entry, instance)
-        .expect("Unable to load Win32Surface extension");
+    for (index, &command_buffer) in command_buffers.iter().enumerate() {
+        let begin_info = vk::CommandBufferBeginInfo {
+            s_type: vk::StructureType::CommandBufferBeginInfo,
+            p_next: ptr::null(),
+            flags: vk::COMMAND_BUFFER_USAGE_SIMULTANEOUS_USE_BIT,
+            p_inheritance_info: ptr::null(),
+        };
+
+        unsafe {
+            device.begin_command_buffer(command_buffer, &begin_info)
+                .expect("Unable to begin command buffer!");
+        }
+
+        let clear_color = vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.39, 0.58, 0.93, 1.0],
+            },
+        };
+
+        let render_pass_info = vk::RenderPassBeginInfo {
+            s_type: vk::StructureType::RenderPassBeginInfo,
+            p_next: ptr::null(),
+            render_pass: draw_info.render_pass,
+            framebuffer: draw_info.framebuffers[index],
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: 0,
+                    y: 0,
+                },
+                extent: draw_info.extent,
+            },
+            clear_value_count: 1,
+            p_clear_values: &clear_color,
+        };
+
+        unsafe {
+            device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::Inline);
+
+            // Beginning the render pass is what clears the image, so in
+            // clear-only mode we stop right there.
+            if !draw_info.clear_only {
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::Graphics, draw_info.pipeline);
+                device.cmd_set_viewport(command_buffer, 0, &[draw_info.viewport]);
+                device.cmd_set_scissor(command_buffer, &[draw_info.scissor]);
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::Graphics,
+                    draw_info.pipeline_layout,
+                    0,
+                    &[draw_info.descriptor_sets[index]],
+                    &[],
+                );
+                device.cmd_bind_index_buffer(command_buffer, draw_info.index_buffer, 0, vk::IndexType::Uint16);
+                device.cmd_draw_indexed(command_buffer,
+                    draw_info.index_count, // index_count
+                    1, // instance_count
+                    0, // first_index
+                    0, // vertex_offset
+                    0, // first_instance
+                );
+            }
+
+            device.cmd_end_render_pass(command_buffer);
+
+            device.end_command_buffer(command_buffer)
+                .expect("Unable to end command buffer!");
+        }
+    }
+
+    command_buffers
+}
+
+// When what we draw changes, the command buffers we recorded earlier are out of
+// date. We can't touch them while the GPU might still be using them, so we wait
+// for it to finish, throw them out, and record new ones.
+fn refresh_command_buffers(
+    device: &Device<V1_0>,
+    command_pool: vk::CommandPool,
+    command_buffers: &mut Vec<vk::CommandBuffer>,
+    draw_info: &DrawInfo,
+) {
+    device.device_wait_idle()
+        .expect("Unable to wait for device to idle!");
 
     unsafe {
-        win32_surface_extension.create_win32_surface_khr(&win32_create_info, None)
+        device.free_command_buffers(command_pool, command_buffers);
     }
+
+    *command_buffers = record_command_buffers(device, command_pool, draw_info);
 }
 
+// Validation messages can optionally be collected here as well as printed,
+// which lets us check whether an operation triggered any validation errors.
+type CapturedMessages = Arc<Mutex<Vec<String>>>;
+
 // The signature of this function is important -- we pass it to the debug
 // callback extension below.
+//
+// user_data is whatever we passed as p_user_data when we set up the callback:
+// either null, or a pointer to the Mutex inside a CapturedMessages.
 unsafe extern "system" fn vulkan_debug_callback(
     _flags: vk::DebugReportFlagsEXT,
     _obj_type: vk::DebugReportObjectTypeEXT,
@@ -72,27 +658,72 @@ unsafe extern "system" fn vulkan_debug_callback(
     _code: vk::int32_t,
     _layer_prefix: *const vk::c_char,
     p_message: *const vk::c_char,
-    _user_data: *mut vk::c_void,
+    user_data: *mut vk::c_void,
 ) -> u32 {
-    println!("{:?}", CStr::from_ptr(p_message));
+    let message = CStr::from_ptr(p_message);
+    println!("{:?}", message);
+
+    if !user_data.is_null() {
+        let captured = &*(user_data as *const Mutex<Vec<String>>);
+
+        // Validation layers can call us from any thread, hence the Mutex.
+        if let Ok(mut captured) = captured.lock() {
+            captured.push(message.to_string_lossy().into_owned());
+        }
+    }
+
     1
 }
 
-fn set_up_debug_callback(debug_report_extension: &DebugReport) -> vk::DebugReportCallbackEXT {
+// Which kinds of debug messages we subscribe to. Each level includes
+// everything from the levels below it; pressing V cycles through them.
+const DEBUG_SEVERITY_LEVELS: usize = 4;
+const DEFAULT_DEBUG_SEVERITY: usize = 1;
+
+fn debug_severity_flags(level: usize) -> vk::DebugReportFlagsEXT {
+    let mut flags = vk::DEBUG_REPORT_ERROR_BIT_EXT;
+
+    if level >= 1 {
+        flags |= vk::DEBUG_REPORT_WARNING_BIT_EXT | vk::DEBUG_REPORT_PERFORMANCE_WARNING_BIT_EXT;
+    }
+
+    if level >= 2 {
+        flags |= vk::DEBUG_REPORT_INFORMATION_BIT_EXT;
+    }
+
+    if level >= 3 {
+        flags |= vk::DEBUG_REPORT_DEBUG_BIT_EXT;
+    }
+
+    flags
+}
+
+// If captured_messages is given, it must outlive the callback we create here,
+// since the callback holds on to a raw pointer into it.
+fn set_up_debug_callback(
+    debug_report_extension: &DebugReport,
+    flags: vk::DebugReportFlagsEXT,
+    captured_messages: Option<&CapturedMessages>,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) -> vk::DebugReportCallbackEXT {
+    let user_data = match captured_messages {
+        Some(captured) => &**captured as *const Mutex<Vec<String>> as *mut vk::c_void,
+        None => ptr::null_mut(),
+    };
+
     // Pick and choose what kind of debug messages we want to subscribe to and
     // pipe them to vulkan_debug_callback.
     let debug_info = vk::DebugReportCallbackCreateInfoEXT {
         s_type: vk::StructureType::DebugReportCallbackCreateInfoExt,
         p_next: ptr::null(),
-        flags: vk::DEBUG_REPORT_ERROR_BIT_EXT | vk::DEBUG_REPORT_WARNING_BIT_EXT
-            | vk::DEBUG_REPORT_PERFORMANCE_WARNING_BIT_EXT,
+        flags: flags,
         pfn_callback: vulkan_debug_callback,
-        p_user_data: ptr::null_mut(),
+        p_user_data: user_data,
     };
 
     unsafe {
         debug_report_extension
-            .create_debug_report_callback_ext(&debug_info, None)
+            .create_debug_report_callback_ext(&debug_info, allocation_callbacks)
             .expect("Unable to attach DebugReport callback!")
     }
 }
@@ -105,6 +736,7 @@ fn main() {
     let window = winit::WindowBuilder::new()
         .with_title("Try Ash")
         .with_dimensions(window_width, window_height)
+        .with_transparency(TRANSPARENT_WINDOW)
         .build(&events_loop)
         .expect("Unable to construct winit window!");
 
@@ -118,14 +750,79 @@ fn main() {
     let app_name = CString::new("TryAsh").unwrap();
     let raw_name = app_name.as_ptr();
 
-    // Right now, we unconditionally load the validation layers, which rely on
-    // the LunarG Vulkan SDK being installed.
-    let layer_names = [CString::new("VK_LAYER_LUNARG_standard_validation").unwrap()];
+    let args = std::env::args().collect::<Vec<_>>();
+
+    // Validation layers catch API misuse, but they're slow and only come with
+    // the Vulkan SDK. We turn them on in debug builds by default, and
+    // --validation or --no-validation overrides that either way.
+    let want_validation = if args.iter().any(|arg| arg == "--no-validation") {
+        false
+    } else {
+        args.iter().any(|arg| arg == "--validation") || cfg!(debug_assertions)
+    };
+
+    let validation_layer_name = CString::new("VK_LAYER_LUNARG_standard_validation").unwrap();
+
+    // Asking for a layer or extension that isn't installed makes instance
+    // creation fail, so we check what's available first.
+    let available_layers = entry
+        .enumerate_instance_layer_properties()
+        .expect("Unable to enumerate instance layers!")
+        .iter()
+        .map(|layer| unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) }.to_owned())
+        .collect::<Vec<_>>();
+
+    let available_extensions = entry
+        .enumerate_instance_extension_properties()
+        .expect("Unable to enumerate instance extensions!")
+        .iter()
+        .map(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) }.to_owned())
+        .collect::<Vec<_>>();
+
+    let validation_available = available_layers.contains(&validation_layer_name);
+
+    if want_validation && !validation_available {
+        println!("Warning: validation layers aren't installed, running without them");
+    }
+
+    let mut layer_names = if want_validation && validation_available {
+        vec![validation_layer_name]
+    } else {
+        Vec::new()
+    };
+
+    // Extra layers and extensions can be turned on from the command line with
+    // --layer and --extension, which is handy for tools like
+    // VK_LAYER_LUNARG_api_dump. We skip any the loader doesn't know about.
+    for name in arg_values(&args, "--layer") {
+        let name = CString::new(name).unwrap();
+
+        if !available_layers.contains(&name) {
+            println!("Warning: instance layer {:?} isn't installed, skipping it", name);
+        } else if !layer_names.contains(&name) {
+            layer_names.push(name);
+        }
+    }
+
+    let mut extra_extension_names = Vec::new();
+
+    for name in arg_values(&args, "--extension") {
+        let name = CString::new(name).unwrap();
+
+        if available_extensions.contains(&name) {
+            extra_extension_names.push(name);
+        } else {
+            println!("Warning: instance extension {:?} isn't available, skipping it", name);
+        }
+    }
+
     let layers_names_raw: Vec<*const i8> = layer_names
         .iter()
         .map(|layer_name| layer_name.as_ptr())
         .collect();
-    let extension_names_raw = extension_names();
+
+    let mut extension_names_raw = platform::extension_names(&window);
+    extension_names_raw.extend(extra_extension_names.iter().map(|name| name.as_ptr()));
 
     let app_info = vk::ApplicationInfo {
         p_application_name: raw_name,
@@ -148,132 +845,491 @@ fn main() {
         enabled_extension_count: extension_names_raw.len() as u32,
     };
 
-    let instance = unsafe {
-        entry
-            .create_instance(&create_info, None)
-            .expect("Unable to create Vulkan instance")
-    };
+    // This has to outlive every object we create with it, so we set it up
+    // before our instance.
+    let allocation_tracker = if TRACK_HOST_ALLOCATIONS {
+        Some(AllocationTracker::new())
+    } else {
+        None
+    };
+
+    let allocator = allocation_tracker.as_ref().map(|tracker| tracker.callbacks());
+
+    let instance = retry_transient(
+        || unsafe { entry.create_instance(&create_info, allocator) },
+        |error| match *error {
+            InstanceError::VkError(result) => is_transient_failure(result),
+            _ => false,
+        },
+    ).expect("Unable to create Vulkan instance");
+
+    // Load VK_EXT_debug_report extension
+    let debug_report_extension = DebugReport::new(&entry, &instance)
+        .expect("Unable to load DebugReport extension");
+
+    // Keep a record of every validation message we get, so that we can report
+    // how many there were when we exit.
+    let captured_messages = CapturedMessages::default();
+    let mut debug_severity = DEFAULT_DEBUG_SEVERITY;
+    let mut debug_callback = set_up_debug_callback(
+        &debug_report_extension,
+        debug_severity_flags(debug_severity),
+        Some(&captured_messages),
+        allocator,
+    );
+
+    // Load VK_KHR_surface extension
+    let surface_extension = Surface::new(&entry, &instance)
+        .expect("Unable to load the Surface extension");
+
+    let surface = platform::create_surface(&entry, &instance, &window, allocator)
+        .expect("Failed to create surface!");
+
+    // Grab a list of physical devices we can use with our instance.
+    let physical_devices = instance
+        .enumerate_physical_devices()
+        .expect("Failed to enumerate physical devices!");
+
+    // Our device needs to support the Swapchain extension.
+    let required_device_extensions = [Swapchain::name()];
+
+    // Software renderers like lavapipe and SwiftShader show up as CPU devices.
+    // They're far too slow to pick by accident, but handy on machines without
+    // a GPU, so we only consider them with --allow-software.
+    let allow_software = args.iter().any(|arg| arg == "--allow-software");
+
+    // For a given physical device, attempt to locate queue families that can
+    // draw and present to our surface. Devices that are missing any of our
+    // required extensions or can't show anything on our surface are no good
+    // to us at all, and we say why.
+    let find_queue_families = |physical_device: vk::PhysicalDevice| -> Result<QueueFamilies, String> {
+        let device_type = instance.get_physical_device_properties(physical_device).device_type;
+
+        if device_type == vk::PhysicalDeviceType::Cpu && !allow_software {
+            return Err("is a software renderer, pass --allow-software to use it".to_string());
+        }
+
+        if !check_device_extension_support(&instance, physical_device, &required_device_extensions) {
+            return Err(format!("is missing one of the extensions {:?}", required_device_extensions));
+        }
+
+        let surface_formats = surface_extension
+            .get_physical_device_surface_formats_khr(physical_device, surface)
+            .unwrap_or_default();
+
+        if surface_formats.is_empty() {
+            return Err("doesn't support any formats for our surface".to_string());
+        }
+
+        let queue_families = instance.get_physical_device_queue_family_properties(physical_device);
+
+        // Rust uses usize for array indexing, Vulkan uses u32.
+        let supports_graphics = |index: usize| {
+            queue_families[index].queue_flags.subset(vk::QUEUE_GRAPHICS_BIT)
+        };
+
+        let supports_present = |index: usize| {
+            surface_extension.get_physical_device_surface_support_khr(physical_device, index as u32, surface)
+        };
+
+        // One family that can do both is best, since then our swapchain images
+        // never have to be shared between queues. Almost every device has one.
+        let shared_family = (0..queue_families.len())
+            .find(|&index| supports_graphics(index) && supports_present(index));
+
+        if let Some(index) = shared_family {
+            return Ok(QueueFamilies {
+                graphics: index as u32,
+                present: index as u32,
+            });
+        }
+
+        let graphics_family = (0..queue_families.len())
+            .find(|&index| supports_graphics(index))
+            .ok_or_else(|| "has no queue family that can draw".to_string())?;
+
+        let present_family = (0..queue_families.len())
+            .find(|&index| supports_present(index))
+            .ok_or_else(|| "has no queue family that can present to our surface".to_string())?;
+
+        Ok(QueueFamilies {
+            graphics: graphics_family as u32,
+            present: present_family as u32,
+        })
+    };
+
+    // When more than one device works, we'd rather use a dedicated GPU than
+    // an integrated one, and either of those over anything else.
+    let device_score = |physical_device: vk::PhysicalDevice| {
+        let properties = instance.get_physical_device_properties(physical_device);
+
+        match properties.device_type {
+            vk::PhysicalDeviceType::DiscreteGpu => 3,
+            vk::PhysicalDeviceType::IntegratedGpu => 2,
+            vk::PhysicalDeviceType::VirtualGpu => 1,
+            _ => 0,
+        }
+    };
+
+    // Device names come back as fixed-size C strings.
+    let device_name = |physical_device: vk::PhysicalDevice| {
+        let properties = instance.get_physical_device_properties(physical_device);
+
+        unsafe {
+            CStr::from_ptr(properties.device_name.as_ptr())
+                .to_string_lossy()
+                .into_owned()
+        }
+    };
+
+    // With --list-gpus, we print out every device we found and stop there.
+    if args.iter().any(|arg| arg == "--list-gpus") {
+        for (index, &physical_device) in physical_devices.iter().enumerate() {
+            let properties = instance.get_physical_device_properties(physical_device);
+
+            // Vendors are free to pack their driver version however they like,
+            // so this is only a best guess using Vulkan's own scheme: 10 bits
+            // of major version, 10 of minor, and 12 of patch.
+            let driver_version = properties.driver_version;
+
+            println!(
+                "{}: '{}' ({:?}), driver version {}.{}.{}",
+                index,
+                device_name(physical_device),
+                properties.device_type,
+                driver_version >> 22,
+                (driver_version >> 12) & 0x3ff,
+                driver_version & 0xfff,
+            );
+        }
+
+        unsafe {
+            surface_extension.destroy_surface_khr(surface, allocator);
+            debug_report_extension.destroy_debug_report_callback_ext(debug_callback, allocator);
+            instance.destroy_instance(allocator);
+        }
+
+        return;
+    }
+
+    // When picking a device goes wrong, --verbose-init dumps everything we
+    // know about each one before we choose.
+    if args.iter().any(|arg| arg == "--verbose-init") {
+        for &physical_device in &physical_devices {
+            print_device_diagnostics(&instance, &surface_extension, surface, physical_device);
+        }
+    }
+
+    // On machines with more than one GPU, it's handy to be able to pick one
+    // by hand. `--gpu <index or name>` picks a device either by its position
+    // in the list above (see --list-gpus) or as the first device whose name
+    // contains the given text. The VK_DEVICE_INDEX and VK_DEVICE_NAME
+    // environment variables do the same thing.
+    let gpu_arg = arg_values(&args, "--gpu").into_iter().next();
+
+    let requested_device = if let Some(selector) = gpu_arg {
+        let device = match selector.parse::<usize>() {
+            Ok(index) => physical_devices.get(index).cloned(),
+            Err(_) => physical_devices
+                .iter()
+                .cloned()
+                .find(|&physical_device| device_name(physical_device).contains(selector.as_str())),
+        };
+
+        if device.is_none() {
+            println!("--gpu {} doesn't match any physical device, ignoring it.", selector);
+        }
+
+        device
+    } else if let Ok(index) = std::env::var("VK_DEVICE_INDEX") {
+        let device = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| physical_devices.get(index).cloned());
+
+        if device.is_none() {
+            println!("VK_DEVICE_INDEX={} doesn't name a physical device, ignoring it.", index);
+        }
+
+        device
+    } else if let Ok(name) = std::env::var("VK_DEVICE_NAME") {
+        let device = physical_devices
+            .iter()
+            .cloned()
+            .find(|&physical_device| device_name(physical_device).contains(&name));
+
+        if device.is_none() {
+            println!("No physical device matches VK_DEVICE_NAME={}, ignoring it.", name);
+        }
+
+        device
+    } else {
+        None
+    };
+
+    // Even a device we picked by hand needs to be able to do our work.
+    let requested_device = requested_device.and_then(|physical_device| {
+        match find_queue_families(physical_device) {
+            Ok(families) => Some((physical_device, families)),
+            Err(reason) => {
+                println!("Requested device '{}' {}, ignoring it.", device_name(physical_device), reason);
+                None
+            },
+        }
+    });
+
+    // Otherwise, take the best scoring device that works.
+    let (physical_device, queue_families) = requested_device
+        .or_else(|| {
+            let suitable_devices = physical_devices
+                .iter()
+                .filter_map(|&physical_device| {
+                    match find_queue_families(physical_device) {
+                        Ok(families) => Some((physical_device, families)),
+                        Err(reason) => {
+                            println!("Skipping device '{}': it {}", device_name(physical_device), reason);
+                            None
+                        },
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            // max_by_key picks the last of several equally good devices, so we
+            // go backwards to end up with the first one instead.
+            suitable_devices
+                .into_iter()
+                .rev()
+                .max_by_key(|&(physical_device, _)| device_score(physical_device))
+        })
+        .expect("Couldn't find suitable physical device.");
+
+    println!("Using physical device '{}'", device_name(physical_device));
+
+    if instance.get_physical_device_properties(physical_device).device_type == vk::PhysicalDeviceType::Cpu {
+        println!("**********************************************************************");
+        println!("* Warning: rendering on the CPU with a software renderer.            *");
+        println!("* Everything will work, but expect it to be very slow.               *");
+        println!("**********************************************************************");
+    }
 
-    // Load VK_EXT_debug_report extension
-    let debug_report_extension = DebugReport::new(&entry, &instance)
-        .expect("Unable to load DebugReport extension");
+    // Besides the extensions we can't do without, there are some we'll use if
+    // they're around. VK_KHR_maintenance1 lets us use a viewport with a
+    // negative height, which flips Y to match OpenGL's conventions, so we ask
+    // for it when FLIP_VIEWPORT_Y is set.
+    let maintenance1_name = CStr::from_bytes_with_nul(b"VK_KHR_maintenance1\0").unwrap();
 
-    let debug_callback = set_up_debug_callback(&debug_report_extension);
+    let mut optional_device_extensions = Vec::new();
 
-    // Load VK_KHR_surface extension
-    let surface_extension = Surface::new(&entry, &instance)
-        .expect("Unable to load the Surface extension");
+    if FLIP_VIEWPORT_Y {
+        optional_device_extensions.push(maintenance1_name);
+    }
 
-    let surface = create_surface(&entry, &instance, &window)
-        .expect("Failed to create surface!");
+    // Every extension we're turning on. Code further down checks this to see
+    // what it's allowed to use.
+    let mut enabled_device_extensions = required_device_extensions.to_vec();
 
-    // Grab a list of physical devices we can use with our instance.
-    let physical_devices = instance
-        .enumerate_physical_devices()
-        .expect("Failed to enumerate physical devices!");
+    for &name in &optional_device_extensions {
+        if check_device_extension_support(&instance, physical_device, &[name]) {
+            enabled_device_extensions.push(name);
+        } else {
+            println!("Warning: device extension {:?} isn't supported, going without it", name);
+        }
+    }
 
-    // For each physical device, attempt to locate a queue family that supports
-    // all of the features we want.
-    let (physical_device, queue_family_index) = physical_devices
+    println!("Enabled device extensions: {:?}", enabled_device_extensions);
+
+    let flip_viewport_y = FLIP_VIEWPORT_Y && enabled_device_extensions.contains(&maintenance1_name);
+
+    let device_extension_names_raw: Vec<*const i8> = enabled_device_extensions
         .iter()
-        .filter_map(|physical_device| {
-            let queue_families = instance.get_physical_device_queue_family_properties(*physical_device);
+        .map(|name| name.as_ptr())
+        .collect();
 
-            queue_families
-                .iter()
-                .enumerate()
-                .filter_map(|(index, info)| {
-                    // Rust uses usize for array indexing, Vulkan uses u32.
-                    let index = index as u32;
-
-                    // We need a queue that supports graphics and the KHR
-                    // surface extension.
-                    let supports_graphics = info.queue_flags.subset(vk::QUEUE_GRAPHICS_BIT);
-                    let supports_surface = surface_extension.get_physical_device_surface_support_khr(
-                        *physical_device,
-                        index,
-                        surface,
-                    );
-
-                    if supports_graphics && supports_surface {
-                        Some((*physical_device, index))
-                    } else {
-                        None
-                    }
-                })
-                .nth(0)
-        })
-        .nth(0)
-        .expect("Couldn't find suitable physical device.");
+    // Device features have to be turned on explicitly when we create our
+    // device, so we need to know what the device supports first.
+    let supported_features = instance.get_physical_device_features(physical_device);
+    let device_limits = instance.get_physical_device_properties(physical_device).limits;
 
-    // Our device needs to support the Swapchain extension.
-    let device_extension_names_raw = [Swapchain::name().as_ptr()];
+    // We turn on non-solid fill modes whenever we can, since our pipeline
+    // variants need them even if we start out filling polygons. The rest we
+    // only ask for if our settings need them.
+    let mut optional_features = vec![Feature::FillModeNonSolid];
+
+    if LINE_WIDTH != 1.0 {
+        optional_features.push(Feature::WideLines);
+    }
+
+    let wants_depth_bias_clamp = DEPTH_BIAS.map_or(false, |bias| bias.clamp != 0.0);
+
+    if wants_depth_bias_clamp {
+        optional_features.push(Feature::DepthBiasClamp);
+    }
+
+    if POINT_SIZE != 1.0 {
+        optional_features.push(Feature::LargePoints);
+    }
 
-    // We don't specify any extra device features, but this is where they'd go.
-    let physical_device_features = vk::PhysicalDeviceFeatures {
-        ..Default::default()
+    if ROBUST_BUFFER_ACCESS {
+        optional_features.push(Feature::RobustBufferAccess);
+    }
+
+    let enabled_features = features::negotiate(&supported_features, REQUIRED_FEATURES, &optional_features)
+        .unwrap_or_else(|missing| {
+            panic!(
+                "Device '{}' is missing required features: {:?}",
+                device_name(physical_device),
+                missing
+            )
+        });
+
+    println!("Enabled device features: {:?}", enabled_features.enabled());
+
+    // Any width other than 1.0 needs the wide lines feature, even a thinner
+    // one; if we don't have it, stick to 1.0. Otherwise, clamp to the
+    // supported range. The device will also round our width to the nearest
+    // multiple of line_width_granularity.
+    let line_width = if LINE_WIDTH != 1.0 && !enabled_features.contains(Feature::WideLines) {
+        println!("Warning: wide lines aren't supported, using a line width of 1.0");
+        1.0
+    } else {
+        LINE_WIDTH
+            .max(device_limits.line_width_range[0])
+            .min(device_limits.line_width_range[1])
     };
 
-    // We're creating one queue of type `queue_family_index`
-    let queue_priorities = [1.0];
-    let queue_info = vk::DeviceQueueCreateInfo {
-        s_type: vk::StructureType::DeviceQueueCreateInfo,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        queue_family_index: queue_family_index,
-        p_queue_priorities: queue_priorities.as_ptr(),
-        queue_count: queue_priorities.len() as u32,
+    let non_solid_supported = enabled_features.contains(Feature::FillModeNonSolid);
+
+    let polygon_mode = if POLYGON_MODE != vk::PolygonMode::Fill && !non_solid_supported {
+        println!("Warning: polygon mode {:?} isn't supported, filling polygons instead", POLYGON_MODE);
+        vk::PolygonMode::Fill
+    } else {
+        POLYGON_MODE
     };
 
-    // Specify that we want to create a Device using one queue of a single queue
-    // family, specified by queue_info above.
+    // Points work a lot like lines: 1.0 always works, anything else needs a
+    // feature and gets clamped to the supported range.
+    let point_size = if POINT_SIZE != 1.0 && !enabled_features.contains(Feature::LargePoints) {
+        println!("Warning: large points aren't supported, using a point size of 1.0");
+        1.0
+    } else {
+        let range = device_limits.point_size_range;
+
+        if POINT_SIZE < range[0] || POINT_SIZE > range[1] {
+            println!(
+                "Warning: point size {} is outside of the supported range {:?}, clamping it",
+                POINT_SIZE,
+                range,
+            );
+        }
+
+        POINT_SIZE.max(range[0]).min(range[1])
+    };
+
+    let depth_bias = if wants_depth_bias_clamp && !enabled_features.contains(Feature::DepthBiasClamp) {
+        println!("Warning: depth bias clamping isn't supported, leaving the bias unclamped");
+        DEPTH_BIAS.map(|bias| DepthBias { clamp: 0.0, ..bias })
+    } else {
+        DEPTH_BIAS
+    };
+
+    if ROBUST_BUFFER_ACCESS && !enabled_features.contains(Feature::RobustBufferAccess) {
+        println!("Warning: robust buffer access isn't supported, leaving it off");
+    }
+
+    // We're creating one queue from each family we need. Usually that's just
+    // one family, but graphics and present can live in different ones.
+    let queue_priorities = [1.0];
+    let queue_infos = queue_families
+        .unique_indices()
+        .iter()
+        .map(|&family_index| vk::DeviceQueueCreateInfo {
+            s_type: vk::StructureType::DeviceQueueCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            queue_family_index: family_index,
+            p_queue_priorities: queue_priorities.as_ptr(),
+            queue_count: queue_priorities.len() as u32,
+        })
+        .collect::<Vec<_>>();
+
+    // Specify that we want to create a Device using the queues described by
+    // queue_infos above.
     let device_create_info = vk::DeviceCreateInfo {
         s_type: vk::StructureType::DeviceCreateInfo,
         p_next: ptr::null(),
         flags: Default::default(),
-        queue_create_info_count: 1,
-        p_queue_create_infos: &queue_info,
+        queue_create_info_count: queue_infos.len() as u32,
+        p_queue_create_infos: queue_infos.as_ptr(),
         enabled_layer_count: 0,
         pp_enabled_layer_names: ptr::null(),
         enabled_extension_count: device_extension_names_raw.len() as u32,
         pp_enabled_extension_names: device_extension_names_raw.as_ptr(),
-        p_enabled_features: &physical_device_features,
+        p_enabled_features: enabled_features.features(),
     };
 
     // Create our device using our information above.
-    let device: Device<V1_0> = unsafe {
-        instance
-            .create_device(physical_device, &device_create_info, None)
-            .expect("Unable to create Device!")
-    };
+    let device: Device<V1_0> = retry_transient(
+        || unsafe { instance.create_device(physical_device, &device_create_info, allocator) },
+        |error| match *error {
+            DeviceError::VkError(result) => is_transient_failure(result),
+            _ => false,
+        },
+    ).expect("Unable to create Device!");
 
     // Load VK_KHR_swapchain extension
     let swapchain_extension = Swapchain::new(&instance, &device)
         .expect("Unable to load Swapchain extension!");
 
-    // Pull the first queue from the family specified by queue_family_index out
-    // of the device we just created.
-    let present_queue = unsafe {
-        device.get_device_queue(queue_family_index, 0)
-    };
+    // Pull our queues out of the device we just created. These are usually
+    // the same queue.
+    let queues = queue_families.get_queues(&device);
 
-    let surface_formats = surface_extension
+    let mut surface_formats = surface_extension
         .get_physical_device_surface_formats_khr(physical_device, surface)
         .expect("Failed to query supported surface formats!");
 
-    // Blindly pick the first surface format the system reports as supported.
-    // Is this a good idea? I don't know.
+    // A single Undefined format means the surface doesn't care what we use, so
+    // we get to pick our favorite.
+    if surface_formats.len() == 1 && surface_formats[0].format == vk::Format::Undefined {
+        surface_formats[0] = vk::SurfaceFormatKHR {
+            format: vk::Format::B8g8r8a8Srgb,
+            color_space: vk::ColorSpaceKHR::SrgbNonlinear,
+        };
+    }
+
+    surface_formats.sort_by_key(|surface_format| std::cmp::Reverse(surface_format_score(surface_format)));
+
+    // Pick the best surface format that we can actually render into. Surfaces
+    // should only report formats like that, but it doesn't hurt to check.
+    let surface_format_candidates = surface_formats
+        .iter()
+        .map(|surface_format| surface_format.format)
+        .collect::<Vec<_>>();
+
+    let chosen_format = find_supported_format(
+        &instance,
+        physical_device,
+        &surface_format_candidates,
+        vk::ImageTiling::Optimal,
+        vk::FORMAT_FEATURE_COLOR_ATTACHMENT_BIT,
+    ).expect("Unable to find a surface format we can render to!");
+
     let surface_format = surface_formats
-        .get(0)
-        .expect("Unable to find a surface format!");
+        .iter()
+        .find(|surface_format| surface_format.format == chosen_format)
+        .unwrap();
+
+    println!("Using surface format {:?} ({:?})", surface_format.format, surface_format.color_space);
 
     let surface_capabilities = surface_extension
         .get_physical_device_surface_capabilities_khr(physical_device, surface)
         .expect("Unable to query surface capabilities!");
 
-    // Use the minimum number of images that our surface supports, plus one to
-    // handle triple-buffering correctly.
-    let mut desired_image_count = surface_capabilities.min_image_count + 1;
+    // Unless we've been told otherwise, use the minimum number of images that
+    // our surface supports, plus one to handle triple-buffering correctly.
+    let mut desired_image_count = DESIRED_SWAPCHAIN_IMAGES
+        .unwrap_or(surface_capabilities.min_image_count + 1)
+        .max(surface_capabilities.min_image_count);
 
     // If max_image_count is 0, that means the implementation has no limit.
     //
@@ -296,13 +1352,87 @@ fn main() {
         .get_physical_device_surface_present_modes_khr(physical_device, surface)
         .expect("Unable to query surface present modes!");
 
-    // We prefer to use Mailbox mode for presenting, but if it isn't available,
-    // fall back to Fifo, which is guaranteed by the spec to be supported.
-    let present_mode = present_modes
+    // Take the first mode from our preference that the surface supports. Every
+    // preference ends with Fifo, which is guaranteed by the spec to be there.
+    //
+    // A broken driver could still leave Fifo out of the list, so we check
+    // instead of asking for a mode the surface never told us about.
+    let preferred_present_modes = PRESENT_PREFERENCE.present_modes();
+
+    let present_mode = preferred_present_modes
+        .iter()
+        .cloned()
+        .find(|mode| present_modes.contains(mode))
+        .unwrap_or_else(|| {
+            panic!(
+                "No supported present mode! None of {:?} was reported (got {:?})",
+                preferred_present_modes,
+                present_modes
+            )
+        });
+
+    if present_mode != preferred_present_modes[0] {
+        println!(
+            "Warning: present mode {:?} isn't supported, falling back to {:?}",
+            preferred_present_modes[0],
+            present_mode,
+        );
+    }
+
+    println!("Using present mode {:?}", present_mode);
+
+    // Composite alpha decides how the window system blends our images with
+    // whatever is behind the window. Opaque ignores alpha entirely, which is
+    // what we want unless we're trying to be see-through.
+    let composite_alpha_preference = if TRANSPARENT_WINDOW {
+        [
+            vk::COMPOSITE_ALPHA_PRE_MULTIPLIED_BIT_KHR,
+            vk::COMPOSITE_ALPHA_POST_MULTIPLIED_BIT_KHR,
+            vk::COMPOSITE_ALPHA_OPAQUE_BIT_KHR,
+            vk::COMPOSITE_ALPHA_INHERIT_BIT_KHR,
+        ]
+    } else {
+        [
+            vk::COMPOSITE_ALPHA_OPAQUE_BIT_KHR,
+            vk::COMPOSITE_ALPHA_INHERIT_BIT_KHR,
+            vk::COMPOSITE_ALPHA_PRE_MULTIPLIED_BIT_KHR,
+            vk::COMPOSITE_ALPHA_POST_MULTIPLIED_BIT_KHR,
+        ]
+    };
+
+    // The spec guarantees at least one of these bits is supported.
+    let composite_alpha = composite_alpha_preference
         .iter()
         .cloned()
-        .find(|&mode| mode == vk::PresentModeKHR::Mailbox)
-        .unwrap_or(vk::PresentModeKHR::Fifo);
+        .find(|&mode| surface_capabilities.supported_composite_alpha.subset(mode))
+        .expect("Surface doesn't support any composite alpha mode!");
+
+    if TRANSPARENT_WINDOW && composite_alpha == vk::COMPOSITE_ALPHA_OPAQUE_BIT_KHR {
+        println!("Warning: surface doesn't support transparency, window will be opaque.");
+    }
+
+    // Anything beyond rendering into swapchain images (copying out of them for
+    // screenshots, for example) needs extra usage bits, which the surface might
+    // not support. Color attachment usage is always supported.
+    let supported_usage = surface_capabilities.supported_usage_flags;
+    let swapchain_image_usage = SWAPCHAIN_IMAGE_USAGE & supported_usage;
+
+    if swapchain_image_usage != SWAPCHAIN_IMAGE_USAGE {
+        println!(
+            "Warning: requested swapchain image usage {:?}, but the surface only supports {:?}",
+            SWAPCHAIN_IMAGE_USAGE,
+            supported_usage
+        );
+    }
+
+    // If we draw and present from different queue families, our swapchain
+    // images get used by both. Concurrent sharing lets that happen without
+    // transferring ownership of each image back and forth.
+    let (swapchain_sharing_mode, swapchain_family_indices) = if queue_families.is_shared() {
+        (vk::SharingMode::Exclusive, Vec::new())
+    } else {
+        (vk::SharingMode::Concurrent, queue_families.unique_indices())
+    };
 
     // Swapchains need a *lot* of information.
     let swapchain_create_info = vk::SwapchainCreateInfoKHR {
@@ -315,12 +1445,12 @@ fn main() {
         image_format: surface_format.format,
         image_extent: surface_resolution,
         image_array_layers: 1,
-        image_usage: vk::IMAGE_USAGE_COLOR_ATTACHMENT_BIT,
-        image_sharing_mode: vk::SharingMode::Exclusive,
-        queue_family_index_count: 0,
-        p_queue_family_indices: ptr::null(),
+        image_usage: swapchain_image_usage,
+        image_sharing_mode: swapchain_sharing_mode,
+        queue_family_index_count: swapchain_family_indices.len() as u32,
+        p_queue_family_indices: swapchain_family_indices.as_ptr(),
         pre_transform: surface_capabilities.current_transform,
-        composite_alpha: vk::COMPOSITE_ALPHA_OPAQUE_BIT_KHR,
+        composite_alpha: composite_alpha,
         present_mode: present_mode,
         clipped: 1,
         old_swapchain: vk::SwapchainKHR::null(),
@@ -329,7 +1459,7 @@ fn main() {
     // After a long-winded setup, actually create our swapchain
     let swapchain = unsafe {
         swapchain_extension
-            .create_swapchain_khr(&swapchain_create_info, None)
+            .create_swapchain_khr(&swapchain_create_info, allocator)
             .expect("Unable to create swapchain!")
     };
 
@@ -337,6 +1467,17 @@ fn main() {
     let swapchain_images = swapchain_extension.get_swapchain_images_khr(swapchain)
         .expect("Unable to get swapchain images!");
 
+    // We asked for desired_image_count images, but that's only a minimum; the
+    // driver is free to give us more. Everything we create per swapchain image
+    // should be sized using this count, not the one we asked for.
+    let swapchain_image_count = swapchain_images.len() as u32;
+
+    println!(
+        "Requested {} swapchain images, got {}",
+        desired_image_count,
+        swapchain_image_count
+    );
+
     // To use our swapchain images, we need to construct image views that
     // describe how to map color channels, access, etc.
     let swapchain_image_views = swapchain_images
@@ -365,7 +1506,7 @@ fn main() {
             };
 
             let image_view = unsafe {
-                device.create_image_view(&create_info, None)
+                device.create_image_view(&create_info, allocator)
                     .expect("Failed to create image view for swapchain image!")
             };
 
@@ -375,16 +1516,18 @@ fn main() {
 
     // Create our vertex and fragment shader modules.
     let vertex_shader_module = {
+        let code = spirv_words(VERTEX_SHADER);
+
         let create_info = vk::ShaderModuleCreateInfo {
             s_type: vk::StructureType::ShaderModuleCreateInfo,
             p_next: ptr::null(),
             flags: Default::default(),
-            code_size: VERTEX_SHADER.len(),
-            p_code: VERTEX_SHADER.as_ptr() as *const u32,
+            code_size: code.len() * 4,
+            p_code: code.as_ptr(),
         };
 
         let shader_module = unsafe {
-            device.create_shader_module(&create_info, None)
+            device.create_shader_module(&create_info, allocator)
                 .expect("Unable to create vertex shader module!")
         };
 
@@ -392,75 +1535,72 @@ fn main() {
     };
 
     let fragment_shader_module = {
+        let code = spirv_words(FRAGMENT_SHADER);
+
         let create_info = vk::ShaderModuleCreateInfo {
             s_type: vk::StructureType::ShaderModuleCreateInfo,
             p_next: ptr::null(),
             flags: Default::default(),
-            code_size: FRAGMENT_SHADER.len(),
-            p_code: FRAGMENT_SHADER.as_ptr() as *const u32,
+            code_size: code.len() * 4,
+            p_code: code.as_ptr(),
         };
 
         let shader_module = unsafe {
-            device.create_shader_module(&create_info, None)
+            device.create_shader_module(&create_info, allocator)
                 .expect("Unable to create fragment shader module!")
         };
 
         shader_module
     };
 
-    // Now, we'll link our dumb byte buffers (shader modules) together into
-    // shader stages, which are a little bit smarter.
-    let entry_point_name = CString::new("main").unwrap();
-
-    let vertex_pipeline_info = vk::PipelineShaderStageCreateInfo {
-        s_type: vk::StructureType::PipelineShaderStageCreateInfo,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        stage: vk::SHADER_STAGE_VERTEX_BIT,
-        module: vertex_shader_module,
-        p_name: entry_point_name.as_ptr(),
-        p_specialization_info: ptr::null(),
-    };
-
-    let fragment_pipeline_info = vk::PipelineShaderStageCreateInfo {
-        s_type: vk::StructureType::PipelineShaderStageCreateInfo,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        stage: vk::SHADER_STAGE_FRAGMENT_BIT,
-        module: fragment_shader_module,
-        p_name: entry_point_name.as_ptr(),
-        p_specialization_info: ptr::null(),
+    // The vertex shader's only specialization constant is the point size.
+    let vertex_specialization_entries = [
+        vk::SpecializationMapEntry {
+            constant_id: 0,
+            offset: 0,
+            size: std::mem::size_of::<f32>(),
+        },
+    ];
+
+    // The fragment shader needs to know what alpha to write and whether the
+    // compositor wants it premultiplied into the color.
+    let fragment_specialization_data = FragmentSpecialization {
+        alpha: if TRANSPARENT_WINDOW { TRANSPARENT_TRIANGLE_ALPHA } else { 1.0 },
+        premultiplied: if composite_alpha == vk::COMPOSITE_ALPHA_PRE_MULTIPLIED_BIT_KHR {
+            vk::VK_TRUE
+        } else {
+            vk::VK_FALSE
+        },
     };
 
-    let shader_stages = vec![vertex_pipeline_info, fragment_pipeline_info];
-
-    // Next, we need to describe what our vertex data looks like.
-    // Hint: there isn't any!
-    let vertex_input_state = vk::PipelineVertexInputStateCreateInfo {
-        s_type: vk::StructureType::PipelineVertexInputStateCreateInfo,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        vertex_binding_description_count: 0,
-        p_vertex_binding_descriptions: ptr::null(),
-        vertex_attribute_description_count: 0,
-        p_vertex_attribute_descriptions: ptr::null(),
-    };
+    let fragment_specialization_entries = [
+        vk::SpecializationMapEntry {
+            constant_id: 0,
+            offset: 0,
+            size: std::mem::size_of::<f32>(),
+        },
+        vk::SpecializationMapEntry {
+            constant_id: 1,
+            offset: std::mem::size_of::<f32>() as u32,
+            size: std::mem::size_of::<vk::Bool32>(),
+        },
+    ];
 
-    // What kind of geometry are we drawing today?
-    let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo {
-        s_type: vk::StructureType::PipelineInputAssemblyStateCreateInfo,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        topology: vk::PrimitiveTopology::TriangleList,
-        primitive_restart_enable: vk::VK_FALSE,
+    // Define the viewport and scissor we'll render with.
+    //
+    // A flipped viewport starts at the bottom of the image and has a negative
+    // height, so +Y in clip space points up like it does in OpenGL.
+    let (viewport_y, viewport_height) = if flip_viewport_y {
+        (surface_resolution.height as f32, -(surface_resolution.height as f32))
+    } else {
+        (0.0, surface_resolution.height as f32)
     };
 
-    // Define our viewport and scissor to create a viewport state!
     let viewport = vk::Viewport {
         x: 0.0,
-        y: 0.0,
+        y: viewport_y,
         width: surface_resolution.width as f32,
-        height: surface_resolution.height as f32,
+        height: viewport_height,
         min_depth: 0.0,
         max_depth: 0.0,
     };
@@ -473,46 +1613,6 @@ fn main() {
         extent: surface_resolution,
     };
 
-    let viewport_state = vk::PipelineViewportStateCreateInfo {
-        s_type: vk::StructureType::PipelineViewportStateCreateInfo,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        viewport_count: 1,
-        p_viewports: &viewport,
-        scissor_count: 1,
-        p_scissors: &scissor,
-    };
-
-    // Define rasterizer state, with things like depth testing and face culling.
-    let rasterization_state = vk::PipelineRasterizationStateCreateInfo {
-        s_type: vk::StructureType::PipelineRasterizationStateCreateInfo,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        depth_clamp_enable: vk::VK_FALSE,
-        rasterizer_discard_enable: vk::VK_FALSE,
-        polygon_mode: vk::PolygonMode::Fill,
-        line_width: 1.0,
-        cull_mode: vk::CULL_MODE_BACK_BIT,
-        front_face: vk::FrontFace::Clockwise,
-        depth_bias_enable: vk::VK_FALSE,
-        depth_bias_constant_factor: 0.0,
-        depth_bias_clamp: 0.0,
-        depth_bias_slope_factor: 0.0,
-    };
-
-    // We don't want to multisampling, but we have to say so.
-    let multisample_state = vk::PipelineMultisampleStateCreateInfo {
-        s_type: vk::StructureType::PipelineMultisampleStateCreateInfo,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        sample_shading_enable: vk::VK_FALSE,
-        rasterization_samples: vk::SAMPLE_COUNT_1_BIT,
-        min_sample_shading: 1.0,
-        p_sample_mask: ptr::null(),
-        alpha_to_coverage_enable: vk::VK_FALSE,
-        alpha_to_one_enable: vk::VK_FALSE,
-    };
-
     // Specify color blending, currently turned off.
     let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
         color_write_mask: vk::COLOR_COMPONENT_R_BIT | vk::COLOR_COMPONENT_G_BIT | vk::COLOR_COMPONENT_B_BIT |
@@ -526,29 +1626,51 @@ fn main() {
         alpha_blend_op: vk::BlendOp::Add,
     };
 
-    let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
-        s_type: vk::StructureType::PipelineColorBlendStateCreateInfo,
+    // Our fragment shader reads a uniform buffer at binding 0, and our vertex
+    // shader reads its transforms from another one at binding 1. Descriptor set
+    // layouts describe the shape of those bindings to the pipeline.
+    let uniform_bindings = [
+        vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::UniformBuffer,
+            descriptor_count: 1,
+            stage_flags: vk::SHADER_STAGE_FRAGMENT_BIT,
+            p_immutable_samplers: ptr::null(),
+        },
+        vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::UniformBuffer,
+            descriptor_count: 1,
+            stage_flags: vk::SHADER_STAGE_VERTEX_BIT,
+            p_immutable_samplers: ptr::null(),
+        },
+    ];
+
+    let descriptor_set_layout_info = vk::DescriptorSetLayoutCreateInfo {
+        s_type: vk::StructureType::DescriptorSetLayoutCreateInfo,
         p_next: ptr::null(),
         flags: Default::default(),
-        logic_op_enable: vk::VK_FALSE,
-        logic_op: vk::LogicOp::Copy,
-        attachment_count: 1,
-        p_attachments: &color_blend_attachment,
-        blend_constants: [0.0, 0.0, 0.0, 0.0],
+        binding_count: uniform_bindings.len() as u32,
+        p_bindings: uniform_bindings.as_ptr(),
+    };
+
+    let descriptor_set_layout = unsafe {
+        device.create_descriptor_set_layout(&descriptor_set_layout_info, allocator)
+            .expect("Unable to create descriptor set layout!")
     };
 
     let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
         s_type: vk::StructureType::PipelineLayoutCreateInfo,
         p_next: ptr::null(),
         flags: Default::default(),
-        set_layout_count: 0,
-        p_set_layouts: ptr::null(),
+        set_layout_count: 1,
+        p_set_layouts: &descriptor_set_layout,
         push_constant_range_count: 0,
         p_push_constant_ranges: ptr::null(),
     };
 
     let pipeline_layout = unsafe {
-        device.create_pipeline_layout(&pipeline_layout_info, None)
+        device.create_pipeline_layout(&pipeline_layout_info, allocator)
             .expect("Unable to create pipeline layout!")
     };
 
@@ -607,40 +1729,72 @@ fn main() {
     };
 
     let render_pass = unsafe {
-        device.create_render_pass(&render_pass_info, None)
+        device.create_render_pass(&render_pass_info, allocator)
             .expect("Failed to create render pass!")
     };
 
     // This is what the last hundreds of lines have been leading up to: actually
-    // creating a graphics pipeline.
+    // creating a graphics pipeline. We describe everything we've set up so far,
+    // plus the rest of the fixed-function state, to a builder.
     //
     // At this point, we still haven't actually accomplished anything, though.
-    let pipeline_info = vk::GraphicsPipelineCreateInfo {
-        s_type: vk::StructureType::GraphicsPipelineCreateInfo,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        stage_count: shader_stages.len() as u32,
-        p_stages: shader_stages.as_ptr(),
-        p_vertex_input_state: &vertex_input_state,
-        p_input_assembly_state: &input_assembly_state,
-        p_viewport_state: &viewport_state,
-        p_rasterization_state: &rasterization_state,
-        p_multisample_state: &multisample_state,
-        p_depth_stencil_state: ptr::null(),
-        p_color_blend_state: &color_blend_state,
-        p_dynamic_state: ptr::null(),
-        p_tessellation_state: ptr::null(),
-        layout: pipeline_layout,
-        render_pass: render_pass,
-        subpass: 0,
-        base_pipeline_handle: vk::Pipeline::null(),
-        base_pipeline_index: -1,
-    };
+    let pipeline_builder = GraphicsPipelineBuilder::new()
+        // Link our dumb byte buffers (shader modules) together into shader
+        // stages, which are a little bit smarter.
+        .shader_stages(vec![
+            ShaderStage::new(vk::SHADER_STAGE_VERTEX_BIT, vertex_shader_module)
+                .specialization(&vertex_specialization_entries, &point_size),
+            ShaderStage::new(vk::SHADER_STAGE_FRAGMENT_BIT, fragment_shader_module)
+                .specialization(&fragment_specialization_entries, &fragment_specialization_data),
+        ])
+        // Next, we need to describe what our vertex data looks like.
+        // Hint: there isn't any!
+        .vertex_input(&[], &[])
+        // What kind of geometry are we drawing today?
+        .topology(vk::PrimitiveTopology::TriangleList)
+        // Our viewport and scissor are dynamic (see below), but the pipeline
+        // still needs to know how many of each we're going to use.
+        .viewport(viewport, scissor)
+        // Define rasterizer state, with things like face culling.
+        .polygon_mode(polygon_mode)
+        .line_width(line_width)
+        .cull_mode(CULL_MODE, FRONT_FACE)
+        .depth_bias(depth_bias)
+        // We don't want to multisampling, but we have to say so.
+        .samples(vk::SAMPLE_COUNT_1_BIT)
+        .blend(color_blend_attachment)
+        // We don't have a depth buffer, so no depth testing either.
+        .depth_test(None)
+        // The viewport and scissor get set when we record our command buffers
+        // instead, so a new window size doesn't mean new pipelines.
+        .dynamic_states(&[vk::DynamicState::Viewport, vk::DynamicState::Scissor])
+        .layout(pipeline_layout)
+        .render_pass(render_pass, 0);
+
+    // Build every variant the device supports, all sharing one layout and
+    // render pass. They differ only in polygon mode.
+    let pipelines = PIPELINE_VARIANTS
+        .iter()
+        .cloned()
+        .filter(|variant| variant.polygon_mode() == vk::PolygonMode::Fill || non_solid_supported)
+        .map(|variant| {
+            let pipeline = pipeline_builder
+                .clone()
+                .polygon_mode(variant.polygon_mode())
+                .build(&device, allocator);
+
+            (variant, pipeline)
+        })
+        .collect::<HashMap<_, _>>();
 
-    let graphics_pipeline = unsafe {
-        device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
-            .expect("Unable to create graphics pipeline!")[0]
-    };
+    let mut active_variant = PIPELINE_VARIANTS
+        .iter()
+        .cloned()
+        .find(|variant| variant.polygon_mode() == polygon_mode)
+        .expect("POLYGON_MODE doesn't match any pipeline variant!");
+
+    // Print out what we just built, which is handy to paste into bug reports.
+    println!("{}", pipeline_builder.describe(&[color_attachment]));
 
     // Create a framebuffer object for each image in our swapchain!
     let swapchain_framebuffers = swapchain_image_views
@@ -659,7 +1813,7 @@ fn main() {
             };
 
             let framebuffer = unsafe {
-                device.create_framebuffer(&framebuffer_info, None)
+                device.create_framebuffer(&framebuffer_info, allocator)
                     .expect("Unable to create framebuffer!")
             };
 
@@ -667,82 +1821,141 @@ fn main() {
         })
         .collect::<Vec<_>>();
 
+    // Next up is the buffer that actually holds our uniform data. We want to be
+    // able to write to it from the CPU every frame, so we ask for memory that's
+    // host visible, and usually also coherent so that we don't need to flush
+    // our writes (see COHERENT_UNIFORM_MEMORY).
+    let uniform_buffer_size = std::mem::size_of::<FragmentUniforms>() as vk::DeviceSize;
+
+    let memory_properties = instance.get_physical_device_memory_properties(physical_device);
+
+    let uniform_buffer = BufferBuilder::new(uniform_buffer_size, vk::BUFFER_USAGE_UNIFORM_BUFFER_BIT)
+        .host_visible(COHERENT_UNIFORM_MEMORY)
+        .build(&device, &memory_properties, allocator);
+
+    // Our indices never change, so we write them once up front.
+    let index_buffer = IndexBuffer::new(&device, &memory_properties, &QUAD_INDICES, allocator);
+
+    // Our transforms change every frame, and a frame can be in flight while we
+    // set up the next one, so every swapchain image gets its own buffer. That
+    // alone isn't enough: getting an image back from acquire_next_image_khr
+    // doesn't mean the GPU is done with the frame that last drew to it. We wait
+    // on that frame's fence (see image_fences) before writing its buffer.
+    let transform_buffer_size = std::mem::size_of::<TransformUniforms>() as vk::DeviceSize;
+
+    let transform_buffers = (0..swapchain_image_count)
+        .map(|_| {
+            BufferBuilder::new(transform_buffer_size, vk::BUFFER_USAGE_UNIFORM_BUFFER_BIT)
+                .build(&device, &memory_properties, allocator)
+        })
+        .collect::<Vec<_>>();
+
+    // Flushes of non-coherent memory have to cover whole 'atoms', so we round
+    // our flush size up to the device's atom size. Running off the end of the
+    // allocation isn't allowed, but flushing right up to the end of it is.
+    let non_coherent_atom_size = instance
+        .get_physical_device_properties(physical_device)
+        .limits
+        .non_coherent_atom_size;
+
+    let uniform_flush_size = std::cmp::min(
+        (uniform_buffer_size + non_coherent_atom_size - 1) / non_coherent_atom_size * non_coherent_atom_size,
+        uniform_buffer.allocation_size,
+    );
+
+    // We keep the memory mapped for the lifetime of the program, which is
+    // totally fine to do in Vulkan. We map the whole allocation so that any
+    // flushes we do stay inside the mapped range.
+    let uniform_pointer = unsafe {
+        device.map_memory(uniform_buffer.memory, 0, vk::VK_WHOLE_SIZE, vk::MemoryMapFlags::empty())
+            .expect("Unable to map uniform buffer memory!") as *mut FragmentUniforms
+    };
+
+    // Descriptor sets are allocated out of pools, which need to know up front
+    // how many of each kind of descriptor we're going to want. Our allocator
+    // takes care of sizing (and growing) those pools for us; we just tell it
+    // what a single set looks like.
+    let mut descriptor_allocator = DescriptorAllocator::new(
+        &[vk::DescriptorPoolSize {
+            typ: vk::DescriptorType::UniformBuffer,
+            descriptor_count: 2,
+        }],
+        swapchain_image_count,
+        allocator,
+    );
+
+    // One descriptor set per swapchain image, each pointing at the shared
+    // fragment uniforms and that image's own transforms.
+    let mut descriptor_sets = Vec::new();
+
+    for transform_buffer in &transform_buffers {
+        let descriptor_set = descriptor_allocator.allocate(&device, descriptor_set_layout);
+
+        let buffer_descriptors = [
+            vk::DescriptorBufferInfo {
+                buffer: uniform_buffer.buffer,
+                offset: 0,
+                range: uniform_buffer.size,
+            },
+            vk::DescriptorBufferInfo {
+                buffer: transform_buffer.buffer,
+                offset: 0,
+                range: transform_buffer.size,
+            },
+        ];
+
+        let descriptor_writes = buffer_descriptors
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer_descriptor)| vk::WriteDescriptorSet {
+                s_type: vk::StructureType::WriteDescriptorSet,
+                p_next: ptr::null(),
+                dst_set: descriptor_set,
+                dst_binding: binding as u32,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::UniformBuffer,
+                p_image_info: ptr::null(),
+                p_buffer_info: buffer_descriptor,
+                p_texel_buffer_view: ptr::null(),
+            })
+            .collect::<Vec<_>>();
+
+        unsafe {
+            device.update_descriptor_sets(&descriptor_writes, &[]);
+        }
+
+        descriptor_sets.push(descriptor_set);
+    }
+
     // Create a command pool to allocate our command buffers from.
     let command_pool_info = vk::CommandPoolCreateInfo {
         s_type: vk::StructureType::CommandPoolCreateInfo,
         p_next: ptr::null(),
         flags: Default::default(),
-        queue_family_index: queue_family_index,
+        queue_family_index: queue_families.graphics,
     };
 
     let command_pool = unsafe {
-        device.create_command_pool(&command_pool_info, None)
+        device.create_command_pool(&command_pool_info, allocator)
             .expect("Unable to create command pool!")
     };
 
-    let command_buffers_info = vk::CommandBufferAllocateInfo {
-        s_type: vk::StructureType::CommandBufferAllocateInfo,
-        p_next: ptr::null(),
-        command_pool: command_pool,
-        level: vk::CommandBufferLevel::Primary,
-        command_buffer_count: swapchain_framebuffers.len() as u32,
-    };
-
-    let command_buffers = unsafe {
-        device.allocate_command_buffers(&command_buffers_info)
-            .expect("Unable to allocate command buffers!")
+    let mut draw_info = DrawInfo {
+        render_pass: render_pass,
+        framebuffers: &swapchain_framebuffers,
+        extent: surface_resolution,
+        viewport: viewport,
+        scissor: scissor,
+        pipeline: pipelines[&active_variant],
+        pipeline_layout: pipeline_layout,
+        descriptor_sets: &descriptor_sets,
+        index_buffer: index_buffer.buffer.buffer,
+        index_count: index_buffer.count,
+        clear_only: CLEAR_ONLY,
     };
 
-    for (index, &command_buffer) in command_buffers.iter().enumerate() {
-        let begin_info = vk::CommandBufferBeginInfo {
-            s_type: vk::StructureType::CommandBufferBeginInfo,
-            p_next: ptr::null(),
-            flags: vk::COMMAND_BUFFER_USAGE_SIMULTANEOUS_USE_BIT,
-            p_inheritance_info: ptr::null(),
-        };
-
-        unsafe {
-            device.begin_command_buffer(command_buffer, &begin_info)
-                .expect("Unable to begin command buffer!");
-        }
-
-        let clear_color = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.39, 0.58, 0.93, 1.0],
-            },
-        };
-
-        let render_pass_info = vk::RenderPassBeginInfo {
-            s_type: vk::StructureType::RenderPassBeginInfo,
-            p_next: ptr::null(),
-            render_pass: render_pass,
-            framebuffer: swapchain_framebuffers[index],
-            render_area: vk::Rect2D {
-                offset: vk::Offset2D {
-                    x: 0,
-                    y: 0,
-                },
-                extent: surface_resolution,
-            },
-            clear_value_count: 1,
-            p_clear_values: &clear_color,
-        };
-
-        unsafe {
-            device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::Inline);
-            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::Graphics, graphics_pipeline);
-            device.cmd_draw(command_buffer,
-                3, // vertex_count
-                1, // instance_count
-                0, // first_vertex
-                0, // first_instance
-            );
-            device.cmd_end_render_pass(command_buffer);
-
-            device.end_command_buffer(command_buffer)
-                .expect("Unable to end command buffer!");
-        }
-    }
+    let mut command_buffers = record_command_buffers(&device, command_pool, &draw_info);
 
     let semaphore_info = vk::SemaphoreCreateInfo {
         s_type: vk::StructureType::SemaphoreCreateInfo,
@@ -751,36 +1964,200 @@ fn main() {
     };
 
     let image_available_semaphore = unsafe {
-        device.create_semaphore(&semaphore_info, None)
+        device.create_semaphore(&semaphore_info, allocator)
             .expect("Unable to create semaphore!")
     };
 
     let render_finished_semaphore = unsafe {
-        device.create_semaphore(&semaphore_info, None)
+        device.create_semaphore(&semaphore_info, allocator)
             .expect("Unable to create semaphore!")
     };
 
+    // Semaphores only order work on the GPU; fences are how the CPU finds out
+    // that work is done. Each swapchain image gets a fence that's signaled when
+    // the frame drawn to it finishes. They start out signaled so that waiting
+    // on one before its first frame doesn't block forever.
+    let fence_info = vk::FenceCreateInfo {
+        s_type: vk::StructureType::FenceCreateInfo,
+        p_next: ptr::null(),
+        flags: vk::FENCE_CREATE_SIGNALED_BIT,
+    };
+
+    let image_fences = (0..swapchain_image_count)
+        .map(|_| unsafe {
+            device.create_fence(&fence_info, allocator)
+                .expect("Unable to create fence!")
+        })
+        .collect::<Vec<_>>();
+
+    // With the 'renderdoc' feature enabled, F11 captures the next frame.
+    let mut frame_capture = FrameCapture::new();
+
+    // We spin and pulse our quad based on how long we've been running.
+    let start_time = std::time::Instant::now();
+
     // It's main loop time!
     loop {
         let mut quit = false;
-        events_loop.poll_events(|event| {
-            match event {
-                winit::Event::WindowEvent { event: winit::WindowEvent::Closed, .. } => {
-                    quit = true;
+        let mut redraw = EVENT_LOOP_MODE == EventLoopMode::Continuous;
+
+        {
+            let mut handle_event = |event: winit::Event| {
+                match event {
+                    winit::Event::WindowEvent { event: winit::WindowEvent::Closed, .. } => {
+                        quit = true;
+                    },
+                    winit::Event::WindowEvent { event: winit::WindowEvent::Resized(..), .. } |
+                    winit::Event::WindowEvent { event: winit::WindowEvent::Refresh, .. } => {
+                        redraw = true;
+                    },
+                    winit::Event::WindowEvent {
+                        event: winit::WindowEvent::KeyboardInput {
+                            input: winit::KeyboardInput {
+                                state: winit::ElementState::Pressed,
+                                virtual_keycode: Some(keycode),
+                                ..
+                            },
+                            ..
+                        },
+                        ..
+                    } => {
+                        redraw = true;
+
+                        match keycode {
+                            winit::VirtualKeyCode::F11 => frame_capture.request(),
+                            winit::VirtualKeyCode::P => {
+                                // Move on to the next variant we were able
+                                // to build, wrapping around at the end.
+                                let current = PIPELINE_VARIANTS
+                                    .iter()
+                                    .position(|&variant| variant == active_variant)
+                                    .unwrap();
+
+                                active_variant = (1..PIPELINE_VARIANTS.len())
+                                    .map(|offset| PIPELINE_VARIANTS[(current + offset) % PIPELINE_VARIANTS.len()])
+                                    .find(|variant| pipelines.contains_key(variant))
+                                    .unwrap_or(active_variant);
+
+                                draw_info.pipeline = pipelines[&active_variant];
+                                refresh_command_buffers(&device, command_pool, &mut command_buffers, &draw_info);
+
+                                println!("Pipeline variant is now {:?}", active_variant);
+                            },
+                            winit::VirtualKeyCode::C => {
+                                draw_info.clear_only = !draw_info.clear_only;
+                                refresh_command_buffers(&device, command_pool, &mut command_buffers, &draw_info);
+
+                                println!("Clear only mode is now {}", if draw_info.clear_only { "on" } else { "off" });
+                            },
+                            winit::VirtualKeyCode::V => {
+                                // Debug callbacks can't be changed once they're
+                                // created, so we swap ours out for a new one.
+                                debug_severity = (debug_severity + 1) % DEBUG_SEVERITY_LEVELS;
+
+                                unsafe {
+                                    debug_report_extension.destroy_debug_report_callback_ext(debug_callback, allocator);
+                                }
+
+                                debug_callback = set_up_debug_callback(
+                                    &debug_report_extension,
+                                    debug_severity_flags(debug_severity),
+                                    Some(&captured_messages),
+                                    allocator,
+                                );
+
+                                println!("Debug severity is now {:?}", debug_severity_flags(debug_severity));
+                            },
+                            _ => (),
+                        }
+                    },
+                    _ => ()
+                }
+            };
+
+            match EVENT_LOOP_MODE {
+                EventLoopMode::Continuous => events_loop.poll_events(handle_event),
+                EventLoopMode::OnDemand => {
+                    // Block until at least one event shows up, then handle
+                    // anything else that arrived alongside it.
+                    events_loop.run_forever(|event| {
+                        handle_event(event);
+                        winit::ControlFlow::Break
+                    });
+
+                    events_loop.poll_events(handle_event);
                 },
-                _ => ()
             }
-        });
+        }
 
         if quit {
             break;
         }
 
+        if !redraw {
+            continue;
+        }
+
         let image_index = unsafe {
+            let _zone = ProfileZone::new("acquire");
+
             swapchain_extension.acquire_next_image_khr(swapchain, std::u64::MAX, image_available_semaphore, vk::Fence::null())
                 .expect("Unable to acquire next swapchain image!")
         };
 
+        // Since our uniform memory is mapped, updating it is just a memory
+        // write. A frame that's still in flight might observe the new value.
+        // The vec2 resolution at the start never changes, so it reads the same
+        // either way, and the worst a frame can see in the time float after it
+        // is a brightness pulse that's a frame ahead.
+        let elapsed = start_time.elapsed();
+        let time = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1_000_000_000.0;
+
+        let uniforms = FragmentUniforms {
+            // Our swapchain never changes size, so neither does this. If we
+            // ever recreate the swapchain, this needs to follow along.
+            resolution: [surface_resolution.width as f32, surface_resolution.height as f32],
+            time: time,
+        };
+
+        unsafe {
+            ptr::write(uniform_pointer, uniforms);
+        }
+
+        // Without coherent memory, the GPU won't see our write until we flush
+        // it. If we were reading memory the GPU wrote, we'd need to call
+        // invalidate_mapped_memory_ranges before reading instead.
+        if !COHERENT_UNIFORM_MEMORY {
+            let flush_range = vk::MappedMemoryRange {
+                s_type: vk::StructureType::MappedMemoryRange,
+                p_next: ptr::null(),
+                memory: uniform_buffer.memory,
+                offset: 0,
+                size: uniform_flush_size,
+            };
+
+            unsafe {
+                device.flush_mapped_memory_ranges(&[flush_range])
+                    .expect("Unable to flush uniform buffer memory!");
+            }
+        }
+
+        // Wait for the last frame drawn to this image to finish, after which
+        // nothing is reading its transform buffer and we can overwrite it.
+        let image_fence = image_fences[image_index as usize];
+
+        unsafe {
+            device.wait_for_fences(&[image_fence], true, std::u64::MAX)
+                .expect("Unable to wait for fence!");
+            device.reset_fences(&[image_fence])
+                .expect("Unable to reset fence!");
+        }
+
+        let aspect_ratio = surface_resolution.width as f32 / surface_resolution.height as f32;
+        let transforms = TransformUniforms::new(time, aspect_ratio);
+
+        transform_buffers[image_index as usize].write(&device, &[transforms]);
+
         let wait_stages = [vk::PIPELINE_STAGE_COLOR_ATTACHMENT_OUTPUT_BIT];
 
         let submit_info = vk::SubmitInfo {
@@ -795,8 +2172,12 @@ fn main() {
             p_command_buffers: &command_buffers[image_index as usize],
         };
 
+        frame_capture.begin_frame();
+
         unsafe {
-            device.queue_submit(present_queue, &[submit_info], vk::Fence::null())
+            let _zone = ProfileZone::new("submit");
+
+            device.queue_submit(queues.graphics, &[submit_info], image_fence)
                 .expect("Unable to submit to queue!");
         }
 
@@ -812,9 +2193,17 @@ fn main() {
         };
 
         unsafe {
-            swapchain_extension.queue_present_khr(present_queue, &present_info)
+            let _zone = ProfileZone::new("present");
+
+            swapchain_extension.queue_present_khr(queues.present, &present_info)
                 .expect("Unable to present!");
         }
+
+        frame_capture.end_frame();
+
+        // Every second or so, this prints how long acquire, submit, and
+        // present took on average.
+        profile::end_frame();
     }
 
     device.device_wait_idle()
@@ -822,33 +2211,60 @@ fn main() {
 
     // Make sure you clean up after yourself!
     unsafe {
-        device.destroy_semaphore(image_available_semaphore, None);
-        device.destroy_semaphore(render_finished_semaphore, None);
+        device.destroy_semaphore(image_available_semaphore, allocator);
+        device.destroy_semaphore(render_finished_semaphore, allocator);
 
-        device.destroy_command_pool(command_pool, None);
+        for &fence in &image_fences {
+            device.destroy_fence(fence, allocator);
+        }
+
+        device.destroy_command_pool(command_pool, allocator);
 
         for &framebuffer in &swapchain_framebuffers {
-            device.destroy_framebuffer(framebuffer, None);
+            device.destroy_framebuffer(framebuffer, allocator);
+        }
+
+        for &pipeline in pipelines.values() {
+            device.destroy_pipeline(pipeline, allocator);
         }
+        device.destroy_render_pass(render_pass, allocator);
+        device.destroy_pipeline_layout(pipeline_layout, allocator);
 
-        device.destroy_pipeline(graphics_pipeline, None);
-        device.destroy_render_pass(render_pass, None);
-        device.destroy_pipeline_layout(pipeline_layout, None);
+        descriptor_allocator.destroy(&device);
+        device.destroy_descriptor_set_layout(descriptor_set_layout, allocator);
 
-        device.destroy_shader_module(vertex_shader_module, None);
-        device.destroy_shader_module(fragment_shader_module, None);
+        device.unmap_memory(uniform_buffer.memory);
+        uniform_buffer.destroy(&device, allocator);
+        index_buffer.destroy(&device, allocator);
+
+        for transform_buffer in &transform_buffers {
+            transform_buffer.destroy(&device, allocator);
+        }
+
+        device.destroy_shader_module(vertex_shader_module, allocator);
+        device.destroy_shader_module(fragment_shader_module, allocator);
 
         for &image_view in &swapchain_image_views {
-            device.destroy_image_view(image_view, None);
+            device.destroy_image_view(image_view, allocator);
         }
 
-        swapchain_extension.destroy_swapchain_khr(swapchain, None);
+        swapchain_extension.destroy_swapchain_khr(swapchain, allocator);
+
+        device.destroy_device(allocator);
 
-        device.destroy_device(None);
+        surface_extension.destroy_surface_khr(surface, allocator);
+        debug_report_extension.destroy_debug_report_callback_ext(debug_callback, allocator);
+
+        instance.destroy_instance(allocator);
+    }
+
+    if let Some(ref tracker) = allocation_tracker {
+        tracker.report();
+    }
 
-        surface_extension.destroy_surface_khr(surface, None);
-        debug_report_extension.destroy_debug_report_callback_ext(debug_callback, None);
+    let captured_messages = captured_messages.lock().unwrap();
 
-        instance.destroy_instance(None);
+    if !captured_messages.is_empty() {
+        println!("{} validation message(s) were reported this run.", captured_messages.len());
     }
 }
\ No newline at end of file
@@ -0,0 +1,438 @@
+// Creating a graphics pipeline means filling out a big pile of create-info
+// structs that all point at each other. Doing that by hand is a great way to
+// learn what a pipeline is made of (see git history!), but it's easy to end up
+// with a pointer to a temporary that's already gone.
+//
+// GraphicsPipelineBuilder owns all of the data those structs point to, and only
+// assembles the actual Vulkan structs inside of build(), where everything they
+// point at is guaranteed to still be alive.
+
+use std::ffi::CString;
+use std::{mem, ptr, slice};
+
+use ash::{Device, vk};
+use ash::version::{DeviceV1_0, V1_0};
+
+// Depth bias nudges the depth of each fragment, which shadow mapping uses to
+// avoid surfaces shadowing themselves.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthBias {
+    pub constant: f32,
+    pub slope: f32,
+    pub clamp: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DepthTest {
+    pub write: bool,
+    pub compare_op: vk::CompareOp,
+}
+
+// A single shader stage: a shader module, the function to start at, and any
+// specialization constants to bake in.
+#[derive(Clone)]
+pub struct ShaderStage {
+    stage: vk::ShaderStageFlags,
+    module: vk::ShaderModule,
+    entry_point: CString,
+    specialization_entries: Vec<vk::SpecializationMapEntry>,
+    specialization_data: Vec<u8>,
+}
+
+impl ShaderStage {
+    pub fn new(stage: vk::ShaderStageFlags, module: vk::ShaderModule) -> ShaderStage {
+        ShaderStage {
+            stage: stage,
+            module: module,
+            entry_point: CString::new("main").unwrap(),
+            specialization_entries: Vec::new(),
+            specialization_data: Vec::new(),
+        }
+    }
+
+    // Specialization data is just bytes as far as Vulkan is concerned, so we
+    // copy whatever `repr(C)` struct we're given into a byte buffer we own.
+    pub fn specialization<T: Copy>(mut self, entries: &[vk::SpecializationMapEntry], data: &T) -> ShaderStage {
+        let bytes = unsafe {
+            slice::from_raw_parts(data as *const T as *const u8, mem::size_of::<T>())
+        };
+
+        self.specialization_entries = entries.to_vec();
+        self.specialization_data = bytes.to_vec();
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct GraphicsPipelineBuilder {
+    stages: Vec<ShaderStage>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    viewports: Vec<vk::Viewport>,
+    scissors: Vec<vk::Rect2D>,
+    polygon_mode: vk::PolygonMode,
+    line_width: f32,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    depth_bias: Option<DepthBias>,
+    samples: vk::SampleCountFlags,
+    blend_attachments: Vec<vk::PipelineColorBlendAttachmentState>,
+    depth_test: Option<DepthTest>,
+    dynamic_states: Vec<vk::DynamicState>,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    subpass: u32,
+}
+
+impl GraphicsPipelineBuilder {
+    // Starts out with the most boring pipeline possible: filled triangles, no
+    // culling, no blending, no depth testing and no multisampling.
+    pub fn new() -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder {
+            stages: Vec::new(),
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TriangleList,
+            viewports: Vec::new(),
+            scissors: Vec::new(),
+            polygon_mode: vk::PolygonMode::Fill,
+            line_width: 1.0,
+            cull_mode: vk::CullModeFlags::empty(),
+            front_face: vk::FrontFace::Clockwise,
+            depth_bias: None,
+            samples: vk::SAMPLE_COUNT_1_BIT,
+            blend_attachments: Vec::new(),
+            depth_test: None,
+            dynamic_states: Vec::new(),
+            layout: vk::PipelineLayout::null(),
+            render_pass: vk::RenderPass::null(),
+            subpass: 0,
+        }
+    }
+
+    pub fn shader_stages(mut self, stages: Vec<ShaderStage>) -> GraphicsPipelineBuilder {
+        self.stages = stages;
+        self
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> GraphicsPipelineBuilder {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> GraphicsPipelineBuilder {
+        self.topology = topology;
+        self
+    }
+
+    // Adds a viewport and its matching scissor rectangle.
+    pub fn viewport(mut self, viewport: vk::Viewport, scissor: vk::Rect2D) -> GraphicsPipelineBuilder {
+        self.viewports.push(viewport);
+        self.scissors.push(scissor);
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> GraphicsPipelineBuilder {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn line_width(mut self, line_width: f32) -> GraphicsPipelineBuilder {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> GraphicsPipelineBuilder {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn depth_bias(mut self, depth_bias: Option<DepthBias>) -> GraphicsPipelineBuilder {
+        self.depth_bias = depth_bias;
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> GraphicsPipelineBuilder {
+        self.samples = samples;
+        self
+    }
+
+    // Adds blend state for the next color attachment. We need one of these
+    // for every color attachment in our subpass, in the same order.
+    pub fn blend(mut self, attachment: vk::PipelineColorBlendAttachmentState) -> GraphicsPipelineBuilder {
+        self.blend_attachments.push(attachment);
+        self
+    }
+
+    pub fn depth_test(mut self, depth_test: Option<DepthTest>) -> GraphicsPipelineBuilder {
+        self.depth_test = depth_test;
+        self
+    }
+
+    pub fn dynamic_states(mut self, dynamic_states: &[vk::DynamicState]) -> GraphicsPipelineBuilder {
+        self.dynamic_states = dynamic_states.to_vec();
+        self
+    }
+
+    pub fn layout(mut self, layout: vk::PipelineLayout) -> GraphicsPipelineBuilder {
+        self.layout = layout;
+        self
+    }
+
+    pub fn render_pass(mut self, render_pass: vk::RenderPass, subpass: u32) -> GraphicsPipelineBuilder {
+        self.render_pass = render_pass;
+        self.subpass = subpass;
+        self
+    }
+
+    pub fn build(&self, device: &Device<V1_0>, allocation_callbacks: Option<&vk::AllocationCallbacks>) -> vk::Pipeline {
+        // Specialization info has to live somewhere while we point at it, so
+        // we collect it first. The Vec is never resized after this, so the
+        // pointers we take into it below stay valid.
+        let specialization_infos = self.stages
+            .iter()
+            .map(|stage| vk::SpecializationInfo {
+                map_entry_count: stage.specialization_entries.len() as u32,
+                p_map_entries: stage.specialization_entries.as_ptr(),
+                data_size: stage.specialization_data.len(),
+                p_data: stage.specialization_data.as_ptr() as *const vk::c_void,
+            })
+            .collect::<Vec<_>>();
+
+        let shader_stages = self.stages
+            .iter()
+            .zip(&specialization_infos)
+            .map(|(stage, specialization_info)| vk::PipelineShaderStageCreateInfo {
+                s_type: vk::StructureType::PipelineShaderStageCreateInfo,
+                p_next: ptr::null(),
+                flags: Default::default(),
+                stage: stage.stage,
+                module: stage.module,
+                p_name: stage.entry_point.as_ptr(),
+                p_specialization_info: if stage.specialization_entries.is_empty() {
+                    ptr::null()
+                } else {
+                    specialization_info
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let vertex_input_state = vk::PipelineVertexInputStateCreateInfo {
+            s_type: vk::StructureType::PipelineVertexInputStateCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            vertex_binding_description_count: self.vertex_bindings.len() as u32,
+            p_vertex_binding_descriptions: self.vertex_bindings.as_ptr(),
+            vertex_attribute_description_count: self.vertex_attributes.len() as u32,
+            p_vertex_attribute_descriptions: self.vertex_attributes.as_ptr(),
+        };
+
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo {
+            s_type: vk::StructureType::PipelineInputAssemblyStateCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            topology: self.topology,
+            primitive_restart_enable: vk::VK_FALSE,
+        };
+
+        let viewport_state = vk::PipelineViewportStateCreateInfo {
+            s_type: vk::StructureType::PipelineViewportStateCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            viewport_count: self.viewports.len() as u32,
+            p_viewports: self.viewports.as_ptr(),
+            scissor_count: self.scissors.len() as u32,
+            p_scissors: self.scissors.as_ptr(),
+        };
+
+        // If we don't want any depth bias, Vulkan still wants numbers here.
+        let depth_bias = self.depth_bias.unwrap_or(DepthBias {
+            constant: 0.0,
+            slope: 0.0,
+            clamp: 0.0,
+        });
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo {
+            s_type: vk::StructureType::PipelineRasterizationStateCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            depth_clamp_enable: vk::VK_FALSE,
+            rasterizer_discard_enable: vk::VK_FALSE,
+            polygon_mode: self.polygon_mode,
+            line_width: self.line_width,
+            cull_mode: self.cull_mode,
+            front_face: self.front_face,
+            depth_bias_enable: if self.depth_bias.is_some() { vk::VK_TRUE } else { vk::VK_FALSE },
+            depth_bias_constant_factor: depth_bias.constant,
+            depth_bias_clamp: depth_bias.clamp,
+            depth_bias_slope_factor: depth_bias.slope,
+        };
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo {
+            s_type: vk::StructureType::PipelineMultisampleStateCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            sample_shading_enable: vk::VK_FALSE,
+            rasterization_samples: self.samples,
+            min_sample_shading: 1.0,
+            p_sample_mask: ptr::null(),
+            alpha_to_coverage_enable: vk::VK_FALSE,
+            alpha_to_one_enable: vk::VK_FALSE,
+        };
+
+        // Stencil testing is off, but Vulkan wants to know what it would do.
+        let stencil_op = vk::StencilOpState {
+            fail_op: vk::StencilOp::Keep,
+            pass_op: vk::StencilOp::Keep,
+            depth_fail_op: vk::StencilOp::Keep,
+            compare_op: vk::CompareOp::Always,
+            compare_mask: 0,
+            write_mask: 0,
+            reference: 0,
+        };
+
+        let depth_stencil_state = self.depth_test.map(|depth_test| vk::PipelineDepthStencilStateCreateInfo {
+            s_type: vk::StructureType::PipelineDepthStencilStateCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            depth_test_enable: vk::VK_TRUE,
+            depth_write_enable: if depth_test.write { vk::VK_TRUE } else { vk::VK_FALSE },
+            depth_compare_op: depth_test.compare_op,
+            depth_bounds_test_enable: vk::VK_FALSE,
+            stencil_test_enable: vk::VK_FALSE,
+            front: stencil_op,
+            back: stencil_op,
+            min_depth_bounds: 0.0,
+            max_depth_bounds: 1.0,
+        });
+
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            s_type: vk::StructureType::PipelineColorBlendStateCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            logic_op_enable: vk::VK_FALSE,
+            logic_op: vk::LogicOp::Copy,
+            attachment_count: self.blend_attachments.len() as u32,
+            p_attachments: self.blend_attachments.as_ptr(),
+            blend_constants: [0.0, 0.0, 0.0, 0.0],
+        };
+
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            s_type: vk::StructureType::PipelineDynamicStateCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            dynamic_state_count: self.dynamic_states.len() as u32,
+            p_dynamic_states: self.dynamic_states.as_ptr(),
+        };
+
+        let pipeline_info = vk::GraphicsPipelineCreateInfo {
+            s_type: vk::StructureType::GraphicsPipelineCreateInfo,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            stage_count: shader_stages.len() as u32,
+            p_stages: shader_stages.as_ptr(),
+            p_vertex_input_state: &vertex_input_state,
+            p_input_assembly_state: &input_assembly_state,
+            p_viewport_state: &viewport_state,
+            p_rasterization_state: &rasterization_state,
+            p_multisample_state: &multisample_state,
+            p_depth_stencil_state: match depth_stencil_state {
+                Some(ref state) => state,
+                None => ptr::null(),
+            },
+            p_color_blend_state: &color_blend_state,
+            p_dynamic_state: if self.dynamic_states.is_empty() {
+                ptr::null()
+            } else {
+                &dynamic_state
+            },
+            p_tessellation_state: ptr::null(),
+            layout: self.layout,
+            render_pass: self.render_pass,
+            subpass: self.subpass,
+            base_pipeline_handle: vk::Pipeline::null(),
+            base_pipeline_index: -1,
+        };
+
+        unsafe {
+            device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], allocation_callbacks)
+                .expect("Unable to create graphics pipeline!")[0]
+        }
+    }
+
+    // Summarizes the fixed-function state this builder will bake into a
+    // pipeline, along with the formats of the attachments it renders into.
+    //
+    // When something renders incorrectly (or not at all), this is a good thing
+    // to print out and compare against what you *think* you asked Vulkan for.
+    pub fn describe(&self, attachments: &[vk::AttachmentDescription]) -> String {
+        let depth_bias = match self.depth_bias {
+            Some(bias) => format!("constant {}, slope {}, clamp {}", bias.constant, bias.slope, bias.clamp),
+            None => "disabled".to_string(),
+        };
+
+        let depth_test = match self.depth_test {
+            Some(depth_test) => format!("write: {}, compare: {:?}", depth_test.write, depth_test.compare_op),
+            None => "disabled".to_string(),
+        };
+
+        let blending = self.blend_attachments
+            .iter()
+            .map(|attachment| {
+                if attachment.blend_enable == vk::VK_FALSE {
+                    "off".to_string()
+                } else {
+                    format!(
+                        "color {:?} {:?} {:?}, alpha {:?} {:?} {:?}",
+                        attachment.src_color_blend_factor,
+                        attachment.color_blend_op,
+                        attachment.dst_color_blend_factor,
+                        attachment.src_alpha_blend_factor,
+                        attachment.alpha_blend_op,
+                        attachment.dst_alpha_blend_factor,
+                    )
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let attachment_formats = attachments
+            .iter()
+            .map(|attachment| format!("{:?}", attachment.format))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!(
+            "Pipeline state:\n  \
+             topology: {:?}\n  \
+             polygon mode: {:?}\n  \
+             cull mode: {:?}\n  \
+             front face: {:?}\n  \
+             line width: {}\n  \
+             depth bias: {}\n  \
+             blending: [{}]\n  \
+             depth test: {}\n  \
+             samples: {:?}\n  \
+             dynamic state: {:?}\n  \
+             attachment formats: [{}]",
+            self.topology,
+            self.polygon_mode,
+            self.cull_mode,
+            self.front_face,
+            self.line_width,
+            depth_bias,
+            blending,
+            depth_test,
+            self.samples,
+            self.dynamic_states,
+            attachment_formats,
+        )
+    }
+}
@@ -0,0 +1,44 @@
+// Everything that's different between operating systems lives in here, which
+// so far means getting a Vulkan surface for our window. Each platform gets its
+// own submodule with a surface_extension_names() and a create_surface(), and
+// the rest of the program only ever talks to this module.
+//
+// Windows and Linux (X11 and Wayland) are supported right now.
+
+use ash::{Entry, Instance, vk};
+use ash::extensions::{DebugReport, Surface};
+use ash::version::V1_0;
+use winit::Window;
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+use self::windows as os;
+
+#[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
+mod unix;
+#[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
+use self::unix as os;
+
+// The instance extensions we need on this platform. These depend on the window,
+// since on Linux it could be running under either X11 or Wayland.
+pub fn extension_names(window: &Window) -> Vec<*const i8> {
+    let mut names = vec![
+        Surface::name().as_ptr(),
+        DebugReport::name().as_ptr(),
+    ];
+
+    names.extend(os::surface_extension_names(window).iter().map(|name| name.as_ptr()));
+
+    names
+}
+
+// Uses a platform specific extension to create a surface for our window.
+pub fn create_surface(
+    entry: &Entry<V1_0>,
+    instance: &Instance<V1_0>,
+    window: &Window,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) -> Result<vk::SurfaceKHR, vk::Result> {
+    os::create_surface(entry, instance, window, allocation_callbacks)
+}
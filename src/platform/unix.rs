@@ -0,0 +1,62 @@
+use std::ffi::CStr;
+use std::ptr;
+
+use ash::{Entry, Instance, vk};
+use ash::extensions::{WaylandSurface, XlibSurface};
+use ash::version::V1_0;
+use winit::Window;
+use winit::os::unix::WindowExt;
+
+// winit picks Wayland over X11 when both are available, so we need to check
+// which one we actually ended up with.
+pub fn surface_extension_names(window: &Window) -> Vec<&'static CStr> {
+    if window.get_wayland_surface().is_some() {
+        vec![WaylandSurface::name()]
+    } else {
+        vec![XlibSurface::name()]
+    }
+}
+
+pub fn create_surface(
+    entry: &Entry<V1_0>,
+    instance: &Instance<V1_0>,
+    window: &Window,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) -> Result<vk::SurfaceKHR, vk::Result> {
+    if let (Some(display), Some(surface)) = (window.get_wayland_display(), window.get_wayland_surface()) {
+        let wayland_create_info = vk::WaylandSurfaceCreateInfoKHR {
+            s_type: vk::StructureType::WaylandSurfaceCreateInfoKhr,
+            p_next: ptr::null(),
+            flags: Default::default(),
+            display: display as *mut _,
+            surface: surface as *mut _,
+        };
+
+        let wayland_surface_extension = WaylandSurface::new(entry, instance)
+            .expect("Unable to load WaylandSurface extension");
+
+        return unsafe {
+            wayland_surface_extension.create_wayland_surface_khr(&wayland_create_info, allocation_callbacks)
+        };
+    }
+
+    let x11_display = window.get_xlib_display()
+        .expect("Unable to get X11 display!");
+    let x11_window = window.get_xlib_window()
+        .expect("Unable to get X11 window!");
+
+    let xlib_create_info = vk::XlibSurfaceCreateInfoKHR {
+        s_type: vk::StructureType::XlibSurfaceCreateInfoKhr,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        window: x11_window as vk::Window,
+        dpy: x11_display as *mut vk::Display,
+    };
+
+    let xlib_surface_extension = XlibSurface::new(entry, instance)
+        .expect("Unable to load XlibSurface extension");
+
+    unsafe {
+        xlib_surface_extension.create_xlib_surface_khr(&xlib_create_info, allocation_callbacks)
+    }
+}
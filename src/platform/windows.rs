@@ -0,0 +1,41 @@
+use std::ffi::CStr;
+use std::ptr;
+
+use ash::{Entry, Instance, vk};
+use ash::extensions::Win32Surface;
+use ash::version::V1_0;
+use winapi::shared::windef::HWND;
+use winapi::um::winuser::GetWindow;
+use winit::Window;
+use winit::os::windows::WindowExt;
+
+pub fn surface_extension_names(_window: &Window) -> Vec<&'static CStr> {
+    vec![Win32Surface::name()]
+}
+
+pub fn create_surface(
+    entry: &Entry<V1_0>,
+    instance: &Instance<V1_0>,
+    window: &Window,
+    allocation_callbacks: Option<&vk::AllocationCallbacks>,
+) -> Result<vk::SurfaceKHR, vk::Result> {
+    let hwnd = window.get_hwnd() as HWND;
+    let hinstance = unsafe {
+        GetWindow(hwnd, 0) as *const vk::c_void
+    };
+
+    let win32_create_info = vk::Win32SurfaceCreateInfoKHR {
+        s_type: vk::StructureType::Win32SurfaceCreateInfoKhr,
+        p_next: ptr::null(),
+        flags: Default::default(),
+        hinstance: hinstance,
+        hwnd: hwnd as *const vk::c_void,
+    };
+
+    let win32_surface_extension = Win32Surface::new(entry, instance)
+        .expect("Unable to load Win32Surface extension");
+
+    unsafe {
+        win32_surface_extension.create_win32_surface_khr(&win32_create_info, allocation_callbacks)
+    }
+}
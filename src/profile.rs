@@ -0,0 +1,90 @@
+// A tiny CPU profiler for our render loop. Wrap a chunk of code in a
+// ProfileZone and the time spent in it gets recorded when the zone is dropped:
+//
+//     {
+//         let _zone = ProfileZone::new("acquire");
+//         // ...
+//     }
+//
+// Call end_frame() once per frame, and about once a second we print how long
+// each zone took on average.
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+struct Profiler {
+    // Total time spent in each zone since our last report. We use a Vec
+    // instead of a HashMap so that zones print in the order they first ran.
+    zones: Vec<(&'static str, Duration)>,
+    frames: u32,
+    last_report: Instant,
+}
+
+thread_local! {
+    static PROFILER: RefCell<Profiler> = RefCell::new(Profiler {
+        zones: Vec::new(),
+        frames: 0,
+        last_report: Instant::now(),
+    });
+}
+
+pub struct ProfileZone {
+    name: &'static str,
+    start: Instant,
+}
+
+impl ProfileZone {
+    pub fn new(name: &'static str) -> ProfileZone {
+        ProfileZone {
+            name: name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for ProfileZone {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+
+        PROFILER.with(|profiler| {
+            let mut profiler = profiler.borrow_mut();
+
+            if let Some(zone) = profiler.zones.iter_mut().find(|zone| zone.0 == self.name) {
+                zone.1 += elapsed;
+                return;
+            }
+
+            profiler.zones.push((self.name, elapsed));
+        });
+    }
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
+
+// Marks the end of a frame, printing a breakdown of where our time went if
+// it's been at least a second since the last one.
+pub fn end_frame() {
+    PROFILER.with(|profiler| {
+        let mut profiler = profiler.borrow_mut();
+        profiler.frames += 1;
+
+        if profiler.last_report.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+
+        let frames = profiler.frames;
+        let breakdown = profiler.zones
+            .iter()
+            .map(|&(name, total)| format!("{} {:.3}ms", name, as_millis(total) / frames as f64))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        println!("{} frames, average per frame: {}", frames, breakdown);
+
+        profiler.zones.clear();
+        profiler.frames = 0;
+        profiler.last_report = Instant::now();
+    });
+}